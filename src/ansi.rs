@@ -1,12 +1,15 @@
 pub const CLEAR: &str = "\x1B[2J\x1B[1;1H";
-pub const CLEAR_REST: &str = "\x1B[0J";
 pub const RESET: &str = "\x1B[0m";
-pub const ORIGIN: &str = "\x1B[H";
 pub const WHITE: &str = "\x1B[97m";
 pub const BLUE: &str = "\x1B[34m";
 pub const CYAN: &str = "\x1B[36m";
 pub const GRAY: &str = "\x1B[37m";
 pub const RED: &str = "\x1B[31m";
+pub const GREEN: &str = "\x1B[32m";
+pub const YELLOW: &str = "\x1B[33m";
+pub const MAGENTA: &str = "\x1B[35m";
+pub const HIGHLIGHT: &str = "\x1B[1;4m";
+pub const NO_HIGHLIGHT: &str = "\x1B[22;24m";
 /*
 Regular Files: White (\x1B[37m)
 Directories: Blue (\x1B[34m)
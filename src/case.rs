@@ -0,0 +1,10 @@
+/// Smart case: the case of letters is ignored unless `pattern` has an
+/// uppercase one. Shared by the filter, the search and the finder so they
+/// agree on it.
+pub fn smart_case(pattern: &str) -> bool {
+    !pattern.chars().any(char::is_uppercase)
+}
+
+pub fn chars_equal(a: char, b: char, ignore_case: bool) -> bool {
+    a == b || (ignore_case && a.to_lowercase().eq(b.to_lowercase()))
+}
@@ -1,65 +1,133 @@
 use crate::ansi;
 use crate::file::{File, FileType};
-use crate::App;
+use crate::finder::Finder;
+use crate::fuzzy::fuzzy_match;
+use crate::grep::LINE_NUMBER_WIDTH;
+use crate::info::{InfoType, PreviewSize};
+use crate::long_listing::{fields_width, fitting_fields, format_fields};
+use crate::metadata::{format_time, human_size, owner_and_group, permissions_string};
+use crate::sanitize::{escape_invalid_utf8, sanitize_name};
+use crate::width::{display_width, pieces, truncate_highlighted, Piece};
+use crate::{App, Prompt};
+
+use std::ops::Range;
+use std::path::Path;
 
 impl App {
-    pub fn display(&self) {
-        let padding = 9;
-        let space_for_text = self.width - padding;
-        // 20% 40% 40%
-        let first_col_width = space_for_text / 5;
-        let second_col_width = first_col_width * 2;
-        //assert!(self.width > (first_col_width + second_col_width + padding));
-        print!("{}{}", ansi::ORIGIN, ansi::RESET);
-        self.show_breadcrumbs();
+    pub fn display(&mut self) {
+        let (first_col_width, second_col_width) = self.column_widths();
+        if let Some(finder) = &self.finder {
+            let lines = self.finder_lines(finder);
+            for (row, line) in lines.iter().enumerate() {
+                self.screen.draw_line(row, line);
+            }
+            let _ = self.screen.flush();
+            return;
+        }
+        let mut lines = vec![self.breadcrumbs()];
 
         let info_lines = match &self.selection_info {
-            Some(info) => info.lines(),
+            Some(info) => self.preview_rows(info.lines(), second_col_width, self.preview_page()),
             None => Vec::new(),
         };
         let (from, to) = self.rows_to_print(info_lines.len());
         for (i, line) in (from..to).enumerate() {
             let is_selected = line == self.current_selection;
-            let selection_arrow = match is_selected {
-                true => "->",
-                false => "  ",
+            let is_marked = self
+                .current_directory_contents
+                .get(line)
+                .is_some_and(|file| {
+                    self.marked
+                        .contains(&self.current_directory.join(&file.name))
+                });
+            let selection_arrow = match (is_selected, is_marked) {
+                (true, false) => "->",
+                (true, true) => "+>",
+                (false, true) => "+ ",
+                (false, false) => "  ",
             };
 
-            let formatted_current_item =
-                display_file(self.current_directory_contents.get(line), second_col_width);
-
-            let formatted_parent_item =
-                display_file(self.parent_directory_contents.get(line), first_col_width);
-
             // padding is 3 + 2 + 4 characters
-            let first_two_columns = format!(
-                "{} | {selection_arrow} {} | ",
-                formatted_parent_item, formatted_current_item
-            );
+            let first_two_columns = if self.tree_view {
+                // The tree takes the space of the parent column too.
+                let formatted_current_item =
+                    self.display_current_file(line, first_col_width + 3 + second_col_width);
+                format!("{selection_arrow} {} | ", formatted_current_item)
+            } else {
+                let formatted_current_item = self.display_current_file(line, second_col_width);
+                let parent_file = self.parent_directory_contents.get(line);
+                let formatted_parent_item = match self.parent_directory().and_then(|parent| {
+                    self.git_marker(&parent, parent_file.map(|file| file.name.as_os_str()))
+                }) {
+                    Some(marker) => marker + &display_file(parent_file, first_col_width - 2),
+                    None => display_file(parent_file, first_col_width),
+                };
+                format!(
+                    "{} | {selection_arrow} {} | ",
+                    formatted_parent_item, formatted_current_item
+                )
+            };
 
-            let formatted_info_line = truncate_with_ellipsis(
-                info_lines.get(i).unwrap_or(&"".to_string()),
-                second_col_width,
-            );
+            let (info_line, highlights) = info_lines
+                .get(i)
+                .map(|(line, highlights)| (line.as_str(), highlights.as_slice()))
+                .unwrap_or_default();
+            let formatted_info_line = truncate_highlighted(info_line, highlights, second_col_width);
 
-            print!("{first_two_columns}{formatted_info_line}\n\r",);
+            lines.push(format!("{first_two_columns}{formatted_info_line}"));
         }
 
-        //print!("\n\r");
+        lines.push(self.status_line());
+
         for line in &self.debug_messages {
-            print!("{}\n\r", line);
+            lines.push(sanitize_name(line));
+        }
+
+        for (row, line) in lines.iter().enumerate() {
+            self.screen.draw_line(row, line);
+        }
+        let _ = self.screen.flush();
+    }
+
+    /// The widths of the parent column and of the current and preview
+    /// columns: 20% 40% 40% of what is left after the separators.
+    fn column_widths(&self) -> (usize, usize) {
+        let padding = 9;
+        let space_for_text = self.width - padding;
+        let first_col_width = space_for_text / 5;
+        (first_col_width, first_col_width * 2)
+    }
+
+    /// How much of a file the preview needs: down to a page past the scroll
+    /// position, and wide enough for the horizontal scroll or for a line
+    /// wrapped over the whole column. Rounded up to a power of two, so the
+    /// file is read again only after scrolling twice as far.
+    pub fn preview_size(&self) -> PreviewSize {
+        // Not the rows actually shown, which change with the messages below.
+        let page = self.height;
+        let width = self.column_widths().1;
+        let lines = ((self.preview_scroll / page + 2) * page).max(self.preview_search_lines);
+        let columns = match self.soft_wrap {
+            true => width * page,
+            false => width + self.preview_column,
+        };
+        PreviewSize {
+            lines: lines.next_power_of_two(),
+            columns: columns.next_power_of_two(),
         }
-        print!("{}{}", ansi::CLEAR_REST, ansi::RESET);
-        println!();
     }
 
     fn rows_to_print(&self, info_lines_len: usize) -> (usize, usize) {
         let rows_to_show = (self.height - 2) - self.debug_messages.len();
 
+        let parent_len = match self.tree_view {
+            true => 0,
+            false => self.parent_directory_contents.len(),
+        };
         let max_lines = self
             .current_directory_contents
             .len()
-            .max(self.parent_directory_contents.len())
+            .max(parent_len)
             .max(info_lines_len);
 
         if max_lines > rows_to_show {
@@ -80,21 +148,334 @@ impl App {
         }
     }
 
-    fn show_breadcrumbs(&self) {
-        print!("{}\n\r", self.current_directory.display());
+    /// Parts of the name matched by the filter or the search.
+    fn name_highlights(&self, file: &File) -> Vec<Range<usize>> {
+        let name = sanitize_name(&file.display_name());
+        let mut highlights = Vec::new();
+        if let Some(filter) = &self.filter {
+            highlights.extend(filter.find(&name).unwrap_or_default());
+        }
+        if let Some(search) = &self.search {
+            highlights.extend(search.find(&name));
+        }
+        highlights
+    }
+
+    /// Search matches in a preview line, found in its text without the
+    /// colors. Directory previews list names rather than content, so they
+    /// aren't searched.
+    pub fn preview_highlights(&self, line: &str) -> Vec<Range<usize>> {
+        let mut highlights = match (&self.search, &self.selection_info) {
+            (Some(search), Some(info)) if info.info_type != InfoType::Directory => {
+                let (text, offsets) = strip_escapes(line);
+                search
+                    .find(&text)
+                    .into_iter()
+                    .filter(|range| !range.is_empty())
+                    .map(|range| offsets[range.start]..offsets[range.end - 1] + 1)
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        // Replacement previews start with a line number column.
+        if let Some(regex) = self
+            .virtual_listing
+            .as_ref()
+            .and_then(|listing| listing.highlight.as_ref())
+        {
+            if let Some(text) = line.get(LINE_NUMBER_WIDTH..) {
+                highlights.extend(
+                    regex
+                        .find_iter(text)
+                        .map(|m| m.start() + LINE_NUMBER_WIDTH..m.end() + LINE_NUMBER_WIDTH),
+                );
+            }
+        }
+        highlights
+    }
+
+    fn display_current_file(&self, index: usize, max_length: usize) -> String {
+        let file = self.current_directory_contents.get(index);
+        if !self.long_listing {
+            return self.display_current_name(index, max_length);
+        }
+        let fields = fitting_fields(&self.long_listing_fields, max_length);
+        let name = self.display_current_name(index, max_length - fields_width(fields));
+        match file {
+            Some(file) => name + &format_fields(fields, file, &self.current_directory),
+            None => name + &" ".repeat(fields_width(fields)),
+        }
+    }
+
+    /// The name of an entry in the current listing behind its git status, in
+    /// the tree view just the last part of its path behind the guides.
+    fn display_current_name(&self, index: usize, max_length: usize) -> String {
+        let file = self.current_directory_contents.get(index);
+        let (marker, max_length) = match self.git_marker(
+            &self.current_directory,
+            file.map(|file| file.name.as_os_str()),
+        ) {
+            Some(marker) => (marker, max_length - 2),
+            None => (String::new(), max_length),
+        };
+        let tree_file = file.filter(|_| self.tree_view).map(|file| {
+            let name = Path::new(&file.name).file_name().unwrap_or(&file.name);
+            File::new(file.ftype, name.to_os_string())
+        });
+        let file = tree_file.as_ref().or(file);
+        let highlights = file
+            .map(|file| self.name_highlights(file))
+            .unwrap_or_default();
+        if tree_file.is_none() {
+            return marker + &display_highlighted_file(file, &highlights, max_length);
+        }
+        // Deep down only the innermost guides are shown, guides are one
+        // column per character.
+        let guides = self.tree_guides(index);
+        let skipped = guides.chars().count().saturating_sub(max_length / 2);
+        let guides: String = guides.chars().skip(skipped).collect();
+        let guides_width = guides.chars().count();
+        format!(
+            "{marker}{}{}{}{}",
+            ansi::GRAY,
+            guides,
+            ansi::RESET,
+            display_highlighted_file(file, &highlights, max_length - guides_width)
+        )
+    }
+
+    fn status_line(&self) -> String {
+        let mut fields = Vec::new();
+        let selected = self.current_directory_contents.get(self.current_selection);
+        if let Some(metadata) = selected.and_then(|file| file.metadata(&self.current_directory)) {
+            fields.push(permissions_string(metadata));
+            fields.push(owner_and_group(metadata));
+            fields.push(human_size(metadata.len()));
+            if let Ok(mtime) = metadata.modified() {
+                fields.push(format_time(mtime));
+            }
+            if metadata.file_type().is_symlink() {
+                if let Some(target) = self
+                    .selected_item
+                    .as_ref()
+                    .and_then(|path| std::fs::read_link(path).ok())
+                {
+                    fields.push(format!(
+                        "-> {}",
+                        sanitize_name(&escape_invalid_utf8(target.as_os_str()))
+                    ));
+                }
+            }
+        }
+        let position = match self.current_directory_contents.len() {
+            0 => String::from("0/0"),
+            total => format!("{}/{}", self.current_selection + 1, total),
+        };
+        let order = self.sort_order(&self.current_directory);
+        let mut right = vec![order.describe(), position];
+        if let Some(matches) = self.preview_matches.as_ref().filter(|m| m.count > 0) {
+            right.push(format!("{} lines match in preview", matches.count));
+        }
+        if self.hide_ignored {
+            right.push(String::from("[gitignore]"));
+        }
+        if !self.filtered_out.is_empty() {
+            right.push(format!("{} filtered", self.filtered_out.len()));
+        }
+        if !self.marked.is_empty() {
+            right.push(format!("{} marked", self.marked.len()));
+        }
+        if let Some(free) = self.free_space {
+            right.push(format!("{} free", human_size(free)));
+        }
+        let left = fields.join(" ");
+        let right = right.join("  ");
+        let gap = self
+            .width
+            .saturating_sub(display_width(&left) + display_width(&right))
+            .max(2);
+        format!(
+            "{}{left}{}{right}{}",
+            ansi::GRAY,
+            " ".repeat(gap),
+            ansi::RESET
+        )
+    }
+
+    /// The finder takes over the whole screen: the query on top, then the
+    /// best results.
+    fn finder_lines(&self, finder: &Finder) -> Vec<String> {
+        let mut status = format!("{}/{}", finder.match_count(), finder.candidate_count());
+        if finder.is_walking() {
+            status += " (walking…)";
+        } else if finder.is_incomplete() {
+            status += " (too many entries, stopped walking)";
+        }
+        if self.respect_gitignore {
+            status += " [gitignore]";
+        }
+        let mut lines = vec![format!(
+            "> {}_  {}{status}{}",
+            sanitize_name(&finder.query),
+            ansi::GRAY,
+            ansi::RESET
+        )];
+
+        let rows = self.height.saturating_sub(1);
+        let first = (finder.selection + 1).saturating_sub(rows);
+        for (row, candidate) in finder.results.iter().enumerate().skip(first).take(rows) {
+            let highlights: Vec<Range<usize>> =
+                fuzzy_match(&candidate.display, &finder.results_query)
+                    .map(|(_, positions)| positions)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|offset| {
+                        let len = candidate.display[offset..]
+                            .chars()
+                            .next()
+                            .map_or(1, char::len_utf8);
+                        offset..offset + len
+                    })
+                    .collect();
+            let arrow = if row == finder.selection { "->" } else { "  " };
+            let color = if candidate.is_dir {
+                ansi::BLUE
+            } else {
+                ansi::WHITE
+            };
+            lines.push(format!(
+                "{arrow} {color}{}{}",
+                truncate_highlighted(
+                    &candidate.display,
+                    &highlights,
+                    self.width.saturating_sub(3)
+                ),
+                ansi::RESET
+            ));
+        }
+        lines
+    }
+
+    fn breadcrumbs(&self) -> String {
+        let mut breadcrumbs =
+            sanitize_name(&escape_invalid_utf8(self.current_directory.as_os_str()));
+        if let Some(status) = self
+            .git_status
+            .as_ref()
+            .filter(|status| status.contains(&self.current_directory))
+        {
+            breadcrumbs += &format!("  {}{}", ansi::MAGENTA, sanitize_name(&status.branch));
+            if status.ahead > 0 {
+                breadcrumbs += &format!(" ↑{}", status.ahead);
+            }
+            if status.behind > 0 {
+                breadcrumbs += &format!(" ↓{}", status.behind);
+            }
+            breadcrumbs += ansi::RESET;
+        }
+        if let Some(listing) = &self.virtual_listing {
+            breadcrumbs += &format!("  [{}]", sanitize_name(&listing.title));
+            if listing.is_loading() {
+                breadcrumbs += &format!(" (searching… {})", self.current_directory_contents.len());
+            }
+        } else if self.is_loading_listing() {
+            breadcrumbs += &format!(" (loading… {})", self.current_directory_contents.len());
+        }
+        if let Some(filter) = &self.filter {
+            breadcrumbs += &format!(
+                "  {}[{}] /{}{}{}",
+                ansi::GRAY,
+                filter.mode.name(),
+                sanitize_name(&filter.pattern),
+                if self.prompt == Some(Prompt::Filter) {
+                    "_"
+                } else {
+                    ""
+                },
+                ansi::RESET
+            );
+        }
+        if self.prompt == Some(Prompt::Grep) {
+            breadcrumbs += &format!(
+                "  {}grep: {}_{}",
+                ansi::GRAY,
+                sanitize_name(&self.grep_pattern),
+                ansi::RESET
+            );
+        }
+        if self.prompt == Some(Prompt::Find) {
+            breadcrumbs += &format!(
+                "  {}find: {}_{}",
+                ansi::GRAY,
+                sanitize_name(&self.find_query),
+                ansi::RESET
+            );
+        }
+        if self.prompt == Some(Prompt::Commit) {
+            breadcrumbs += &format!(
+                "  {}commit: {}_{}",
+                ansi::GRAY,
+                sanitize_name(&self.commit_message),
+                ansi::RESET
+            );
+        }
+        let action = match self.prompt {
+            Some(Prompt::ConfirmDiscard) => Some("discard changes to"),
+            Some(Prompt::ConfirmTrash) => Some("trash"),
+            _ => None,
+        };
+        if let Some(action) = action {
+            let count = self.confirm_targets.len();
+            let not_shown = match self.confirm_not_shown {
+                0 => String::new(),
+                not_shown => format!(", {not_shown} of them not shown here"),
+            };
+            breadcrumbs += &format!(
+                "  {}{action} {count} file{}{not_shown}? (y/n){}",
+                ansi::RED,
+                if count == 1 { "" } else { "s" },
+                ansi::RESET
+            );
+        }
+        if let Some(search) = &self.search {
+            let invalid = search.regex.is_none() && !search.pattern.is_empty();
+            breadcrumbs += &format!(
+                "  {}search: {}{}{}{}",
+                ansi::GRAY,
+                sanitize_name(&search.pattern),
+                if self.prompt == Some(Prompt::Search) {
+                    "_"
+                } else {
+                    ""
+                },
+                if invalid { " (invalid)" } else { "" },
+                ansi::RESET
+            );
+        }
+        breadcrumbs
     }
 }
 
 pub fn display_file(file: Option<&File>, max_length: usize) -> String {
+    display_highlighted_file(file, &[], max_length)
+}
+
+/// Like `display_file`, with the given byte ranges of the sanitized name
+/// highlighted.
+fn display_highlighted_file(
+    file: Option<&File>,
+    highlights: &[Range<usize>],
+    max_length: usize,
+) -> String {
     if let Some(file) = file {
-        if file.name.starts_with('.') {
-            display_hidden_file(file, max_length)
+        if file.is_hidden() {
+            display_hidden_file(file, highlights, max_length)
         } else {
             match file.ftype {
-                FileType::File => display_normal_file(file, max_length),
-                FileType::Directory => display_directory(file, max_length),
-                FileType::Link => display_link(file, max_length),
-                FileType::Unknown => display_unknown(file, max_length),
+                FileType::File => display_normal_file(file, highlights, max_length),
+                FileType::Directory => display_directory(file, highlights, max_length),
+                FileType::Link => display_link(file, highlights, max_length),
+                FileType::Unknown => display_unknown(file, highlights, max_length),
             }
         }
     } else {
@@ -102,99 +483,58 @@ pub fn display_file(file: Option<&File>, max_length: usize) -> String {
     }
 }
 
-fn display_hidden_file(file: &File, max_length: usize) -> String {
+fn display_hidden_file(file: &File, highlights: &[Range<usize>], max_length: usize) -> String {
     format!(
         "{}{}{}",
         ansi::GRAY,
-        truncate_with_ellipsis(&file.name, max_length),
+        truncate_highlighted(&sanitize_name(&file.display_name()), highlights, max_length),
         ansi::RESET
     )
 }
 
-fn display_normal_file(file: &File, max_length: usize) -> String {
+fn display_normal_file(file: &File, highlights: &[Range<usize>], max_length: usize) -> String {
     format!(
         "{}{}{}",
         ansi::WHITE,
-        truncate_with_ellipsis(&file.name, max_length),
+        truncate_highlighted(&sanitize_name(&file.display_name()), highlights, max_length),
         ansi::RESET
     )
 }
-fn display_directory(file: &File, max_length: usize) -> String {
+fn display_directory(file: &File, highlights: &[Range<usize>], max_length: usize) -> String {
     format!(
         "{}{}{}",
         ansi::BLUE,
-        truncate_with_ellipsis(&file.name, max_length),
+        truncate_highlighted(&sanitize_name(&file.display_name()), highlights, max_length),
         ansi::RESET
     )
 }
-fn display_link(file: &File, max_length: usize) -> String {
+fn display_link(file: &File, highlights: &[Range<usize>], max_length: usize) -> String {
     format!(
         "{}{}{}",
         ansi::CYAN,
-        truncate_with_ellipsis(&file.name, max_length),
+        truncate_highlighted(&sanitize_name(&file.display_name()), highlights, max_length),
         ansi::RESET
     )
 }
-fn display_unknown(file: &File, max_length: usize) -> String {
+fn display_unknown(file: &File, highlights: &[Range<usize>], max_length: usize) -> String {
     format!(
         "{}{}{}",
         ansi::RED,
-        truncate_with_ellipsis(&file.name, max_length),
+        truncate_highlighted(&sanitize_name(&file.display_name()), highlights, max_length),
         ansi::RESET
     )
 }
 
-fn truncate_with_ellipsis(input: &str, max_length: usize) -> String {
-    if max_length < 3 {
-        return "…".to_owned();
-    }
-    let chars_len = &input.chars().count();
-    let wide_chars = &input.chars().filter(|ch| is_wide(*ch)).count();
-    // Offset the number of wide_characters (as if they are 2x size)
-    if chars_len > &(max_length - wide_chars) {
-        format!(
-            "{}…",
-            &input
-                .chars()
-                .take(max_length - 1 - wide_chars)
-                .collect::<String>()
-        )
-    } else {
-        format!("{:<width$}", input, width = max_length - wide_chars)
-    }
-}
-
-fn is_wide(ch: char) -> bool {
-    // TODO: add more scripts
-    let is_kanji = is_char_between_char_range(ch, KANJI_BEG, KANJI_END);
-    let is_katakana = is_char_between_char_range(ch, KATAKANA_BEG, KATAKANA_END);
-    let is_hiragana = is_char_between_char_range(ch, HIRAGANA_BEG, HIRAGANA_END);
-    let is_fullwidth = is_char_between_char_range(ch, FULLWIDTH_BEG, FULLWIDTH_END);
-    let is_cjk_symbol = is_char_between_char_range(ch, CJK_SYMBOL_BEG, CJK_SYMBOL_END);
-    is_hiragana || is_katakana || is_kanji || is_fullwidth || is_cjk_symbol
-}
-
-// https://github.com/kitallis/konj
-// taken from this project's `strings` file.
-pub fn is_char_between_char_range(ch: char, range_beg: char, range_end: char) -> bool {
-    if !(ch >= range_beg && ch <= range_end) {
-        // || ch.is_whitespace()) {
-        return false;
+/// `line` without escape sequences, and the offset in `line` of each of its
+/// bytes.
+fn strip_escapes(line: &str) -> (String, Vec<usize>) {
+    let mut text = String::with_capacity(line.len());
+    let mut offsets = Vec::with_capacity(line.len());
+    for (offset, piece) in pieces(line) {
+        if let Piece::Text(grapheme) = piece {
+            text.push_str(grapheme);
+            offsets.extend(offset..offset + grapheme.len());
+        }
     }
-
-    true
+    (text, offsets)
 }
-
-// https://github.com/kitallis/konj
-// taken from this project's `constants` file.
-pub const HIRAGANA_BEG: char = '\u{3040}';
-pub const HIRAGANA_END: char = '\u{309F}';
-pub const KATAKANA_BEG: char = '\u{30A0}';
-pub const KATAKANA_END: char = '\u{30FF}';
-pub const KANJI_BEG: char = '\u{4E00}';
-pub const KANJI_END: char = '\u{9FAF}';
-pub const FULLWIDTH_BEG: char = '\u{FF01}';
-pub const FULLWIDTH_END: char = '\u{FF60}';
-//Unicode Block “CJK Symbols and Punctuation”
-pub const CJK_SYMBOL_BEG: char = '\u{3000}';
-pub const CJK_SYMBOL_END: char = '\u{303F}';
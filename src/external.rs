@@ -1,12 +1,16 @@
 use anyhow::{anyhow, Error, Result};
 
 use std::{
+    ffi::OsStr,
     io::Read,
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::{Command, Output},
 };
 
-pub fn run_external_command(command: &str, args: &[&str]) -> Result<Option<Vec<String>>> {
+pub fn run_external_command(
+    command: impl AsRef<OsStr>,
+    args: &[impl AsRef<OsStr>],
+) -> Result<Option<Vec<String>>> {
     let output: Output = Command::new(command).args(args).output()?;
 
     // Check if the command was successful
@@ -26,14 +30,14 @@ pub fn run_external_command(command: &str, args: &[&str]) -> Result<Option<Vec<S
     }
 }
 
-pub fn get_media_length(path: &str) -> Result<f32> {
+pub fn get_media_length(path: &Path) -> Result<f32> {
     let command = "ffprobe";
     let args = &[
-        "-show_entries",
-        "format=duration",
-        "-of",
-        "default=noprint_wrappers=1:nokey=1",
-        path,
+        OsStr::new("-show_entries"),
+        OsStr::new("format=duration"),
+        OsStr::new("-of"),
+        OsStr::new("default=noprint_wrappers=1:nokey=1"),
+        path.as_os_str(),
     ];
     let output: Output = Command::new(command).args(args).output()?;
 
@@ -54,9 +58,14 @@ pub fn probably_valid_utf(path: &PathBuf) -> bool {
     if let Ok(mut file) = std::fs::File::open(path) {
         let mut buffer = vec![0; 1024]; // Read 1KB for analysis
         if let Ok(bytes) = file.read(&mut buffer) {
-            // Check if the content is valid UTF-8
-            return std::str::from_utf8(&buffer[..bytes]).is_ok();
+            return probably_valid_utf_bytes(&buffer[..bytes]);
         }
     }
     false
 }
+
+/// The check behind `probably_valid_utf`, for contents that are already read.
+pub fn probably_valid_utf_bytes(contents: &[u8]) -> bool {
+    // Check if the first KB is valid UTF-8
+    std::str::from_utf8(&contents[..contents.len().min(1024)]).is_ok()
+}
@@ -1,16 +1,99 @@
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::ffi::{OsStr, OsString};
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
 
 use walkdir::{DirEntry, WalkDir};
 
+use crate::info::InfoType;
+use crate::sanitize::escape_invalid_utf8;
+use crate::sort::SortOrder;
+
 #[derive(Debug, Clone)]
 pub struct File {
     pub ftype: FileType,
-    pub name: String,
+    pub name: OsString,
+    /// Result of `lstat`, only fetched once something needs it.
+    metadata: OnceCell<Option<Metadata>>,
+    /// Number of entries, for directories in the long listing.
+    entry_count: OnceCell<Option<usize>>,
+    info_type: OnceCell<InfoType>,
 }
 
 impl File {
-    pub fn new(ftype: FileType, name: String) -> Self {
-        Self { ftype, name }
+    pub fn new(ftype: FileType, name: OsString) -> Self {
+        Self {
+            ftype,
+            name,
+            metadata: OnceCell::new(),
+            entry_count: OnceCell::new(),
+            info_type: OnceCell::new(),
+        }
+    }
+
+    pub fn with_metadata(metadata: Metadata, name: OsString) -> Self {
+        Self {
+            ftype: FileType::from(metadata.file_type()),
+            name,
+            metadata: OnceCell::from(Some(metadata)),
+            entry_count: OnceCell::new(),
+            info_type: OnceCell::new(),
+        }
+    }
+
+    pub fn metadata(&self, directory: &Path) -> Option<&Metadata> {
+        self.metadata
+            .get_or_init(|| std::fs::symlink_metadata(directory.join(&self.name)).ok())
+            .as_ref()
+    }
+
+    pub fn entry_count(&self, directory: &Path) -> Option<usize> {
+        *self.entry_count.get_or_init(|| {
+            std::fs::read_dir(directory.join(&self.name))
+                .map(|entries| entries.count())
+                .ok()
+        })
+    }
+
+    /// Forgets what was read from disk, which may have changed since.
+    pub fn clear_cached(&mut self) {
+        self.metadata.take();
+        self.entry_count.take();
+        self.info_type.take();
+    }
+
+    /// Takes over what `other`, a copy of this entry, already read from disk.
+    pub fn fill_from(&self, other: File) {
+        if let Some(metadata) = other.metadata.into_inner() {
+            let _ = self.metadata.set(metadata);
+        }
+        if let Some(info_type) = other.info_type.into_inner() {
+            let _ = self.info_type.set(info_type);
+        }
+    }
+
+    pub fn info_type(&self, directory: &Path) -> InfoType {
+        *self.info_type.get_or_init(|| match self.ftype {
+            FileType::Directory => InfoType::Directory,
+            FileType::Link => InfoType::Link,
+            _ => InfoType::new(&directory.join(&self.name)).unwrap_or(InfoType::Unknown),
+        })
+    }
+
+    pub fn from_entry(entry: &DirEntry) -> Self {
+        File::new(
+            FileType::from(entry.file_type()),
+            entry.file_name().to_owned(),
+        )
+    }
+
+    pub fn display_name(&self) -> Cow<'_, str> {
+        escape_invalid_utf8(&self.name)
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        is_hidden_name(&self.name)
     }
 }
 
@@ -22,6 +105,20 @@ pub enum FileType {
     Unknown,
 }
 
+impl From<std::fs::FileType> for FileType {
+    fn from(ftype: std::fs::FileType) -> Self {
+        if ftype.is_file() {
+            FileType::File
+        } else if ftype.is_dir() {
+            FileType::Directory
+        } else if ftype.is_symlink() {
+            FileType::Link
+        } else {
+            FileType::Unknown
+        }
+    }
+}
+
 pub fn directory_contents(path: &PathBuf, show_hidden: bool) -> Vec<File> {
     let mut files: Vec<File> = WalkDir::new(path)
         .max_depth(1)
@@ -40,43 +137,52 @@ pub fn directory_contents(path: &PathBuf, show_hidden: bool) -> Vec<File> {
                 }
             }
         })
-        .map(|entry| {
-            let ftype = entry.file_type();
-            let ftype = if ftype.is_file() {
-                FileType::File
-            } else if ftype.is_dir() {
-                FileType::Directory
-            } else if ftype.is_symlink() {
-                FileType::Link
-            } else {
-                FileType::Unknown
-            };
-            let name = entry.file_name().to_string_lossy().into_owned();
-            File::new(ftype, name)
-        })
+        .map(|entry| File::from_entry(&entry))
         .collect();
 
-    files.sort_by(|f1, f2| {
-        let dir1 = f1.ftype == FileType::Directory;
-        let dir2 = f2.ftype == FileType::Directory;
-        if dir1 && dir2 {
-            f1.name.cmp(&f2.name)
-        } else if dir1 && !dir2 {
-            std::cmp::Ordering::Less
-        } else if !dir1 && dir2 {
-            std::cmp::Ordering::Greater
-        } else {
-            f1.name.cmp(&f2.name)
-        }
-    });
+    let order = SortOrder::default();
+    files.sort_by(|f1, f2| order.compare(f1, f2, path));
 
     files
 }
 
-fn is_hidden(entry: &DirEntry) -> bool {
-    entry
-        .file_name()
-        .to_str()
-        .map(|s| s.starts_with("."))
-        .unwrap_or(false)
+/// Inserts or replaces `name` in an already sorted listing of `directory`.
+pub fn insert_file(
+    files: &mut Vec<File>,
+    directory: &Path,
+    name: &OsStr,
+    show_hidden: bool,
+    order: &SortOrder,
+) {
+    // Tree and flatten listings name entries by their path.
+    let file_name = Path::new(name).file_name().unwrap_or(name);
+    if !show_hidden && is_hidden_name(file_name) {
+        return;
+    }
+    let Ok(metadata) = std::fs::symlink_metadata(directory.join(name)) else {
+        return;
+    };
+    remove_file(files, name);
+    let file = File::with_metadata(metadata, name.to_os_string());
+    let index = files
+        .binary_search_by(|probe| order.compare(probe, &file, directory))
+        .unwrap_or_else(|index| index);
+    files.insert(index, file);
+}
+
+pub fn remove_file(files: &mut Vec<File>, name: &OsStr) {
+    files.retain(|file| file.name != name);
+}
+
+/// Removes `name` and, in tree and flatten listings, what was inside of it.
+pub fn remove_path(files: &mut Vec<File>, name: &OsStr) {
+    files.retain(|file| !Path::new(&file.name).starts_with(name));
+}
+
+pub fn is_hidden(entry: &DirEntry) -> bool {
+    is_hidden_name(entry.file_name())
+}
+
+pub fn is_hidden_name(name: &OsStr) -> bool {
+    name.as_encoded_bytes().starts_with(b".")
 }
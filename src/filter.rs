@@ -0,0 +1,326 @@
+use std::ops::Range;
+
+use crate::case::{chars_equal, smart_case};
+use crate::file::File;
+use crate::listing::merge_sorted;
+use crate::sanitize::sanitize_name;
+use crate::{App, Prompt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    /// Substring that ignores case unless the pattern has an uppercase letter.
+    SmartCase,
+    Substring,
+    /// `*`, `?` and `[...]` matched against the whole name, with smart case.
+    Glob,
+}
+
+impl FilterMode {
+    pub fn next(self) -> Self {
+        match self {
+            FilterMode::SmartCase => FilterMode::Substring,
+            FilterMode::Substring => FilterMode::Glob,
+            FilterMode::Glob => FilterMode::SmartCase,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterMode::SmartCase => "smart-case",
+            FilterMode::Substring => "substring",
+            FilterMode::Glob => "glob",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Filter {
+    pub pattern: String,
+    pub mode: FilterMode,
+}
+
+impl Filter {
+    /// Byte ranges of `name` that matched, or None if it doesn't match.
+    pub fn find(&self, name: &str) -> Option<Vec<Range<usize>>> {
+        let ignore_case = self.mode != FilterMode::Substring && smart_case(&self.pattern);
+        match self.mode {
+            FilterMode::SmartCase | FilterMode::Substring => {
+                find_substring(name, &self.pattern, ignore_case).map(|range| vec![range])
+            }
+            FilterMode::Glob => match_glob(name, &self.pattern, ignore_case),
+        }
+    }
+
+    pub fn matches(&self, file: &File) -> bool {
+        self.find(&sanitize_name(&file.display_name())).is_some()
+    }
+}
+
+fn find_substring(haystack: &str, needle: &str, ignore_case: bool) -> Option<Range<usize>> {
+    if !ignore_case {
+        return haystack
+            .find(needle)
+            .map(|start| start..start + needle.len());
+    }
+    haystack.char_indices().find_map(|(start, _)| {
+        let mut rest = haystack[start..].char_indices();
+        let mut end = start;
+        for expected in needle.chars() {
+            let (offset, c) = rest.next()?;
+            if !chars_equal(c, expected, true) {
+                return None;
+            }
+            end = start + offset + c.len_utf8();
+        }
+        Some(start..end)
+    })
+}
+
+enum GlobToken {
+    Star,
+    Any,
+    Class {
+        chars: Vec<(char, char)>,
+        negated: bool,
+    },
+    Literal(char),
+}
+
+fn parse_glob(pattern: &str) -> Vec<GlobToken> {
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            '*' => GlobToken::Star,
+            '?' => GlobToken::Any,
+            '[' => {
+                // An unterminated class is taken literally, which is what the
+                // pattern looks like while it is still being typed.
+                let rest: String = chars.clone().collect();
+                match rest.find(']') {
+                    Some(end) if end > 0 => {
+                        let class = &rest[..end];
+                        let (negated, class) = match class.strip_prefix('!') {
+                            Some(class) => (true, class),
+                            None => (false, class),
+                        };
+                        for _ in rest[..=end].chars() {
+                            chars.next();
+                        }
+                        GlobToken::Class {
+                            chars: parse_class(class),
+                            negated,
+                        }
+                    }
+                    _ => GlobToken::Literal('['),
+                }
+            }
+            c => GlobToken::Literal(c),
+        });
+    }
+    tokens
+}
+
+fn parse_class(class: &str) -> Vec<(char, char)> {
+    let chars: Vec<char> = class.chars().collect();
+    let mut ranges = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if i + 2 < chars.len() && chars[i + 1] == '-' {
+            ranges.push((chars[i], chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((chars[i], chars[i]));
+            i += 1;
+        }
+    }
+    ranges
+}
+
+impl GlobToken {
+    fn matches(&self, c: char, ignore_case: bool) -> bool {
+        match self {
+            GlobToken::Star | GlobToken::Any => true,
+            GlobToken::Literal(expected) => chars_equal(c, *expected, ignore_case),
+            GlobToken::Class { chars, negated } => {
+                let in_class = chars.iter().any(|(low, high)| {
+                    (*low..=*high).contains(&c)
+                        || (ignore_case
+                            && c.to_lowercase()
+                                .chain(c.to_uppercase())
+                                .any(|c| (*low..=*high).contains(&c)))
+                });
+                in_class != *negated
+            }
+        }
+    }
+}
+
+/// Matches the whole name, backtracking only to the last `*` so it stays
+/// linear in practice. Returns the ranges matched by everything but stars.
+fn match_glob(name: &str, pattern: &str, ignore_case: bool) -> Option<Vec<Range<usize>>> {
+    let tokens = parse_glob(pattern);
+    let chars: Vec<(usize, char)> = name.char_indices().collect();
+    let mut matched: Vec<Range<usize>> = Vec::new();
+    let (mut t, mut c) = (0, 0);
+    // Token after the last star, the char it was tried at and how many
+    // matches were recorded then.
+    let mut backtrack: Option<(usize, usize, usize)> = None;
+    while c < chars.len() {
+        let (offset, character) = chars[c];
+        match tokens.get(t) {
+            Some(GlobToken::Star) => {
+                t += 1;
+                backtrack = Some((t, c, matched.len()));
+            }
+            Some(token) if token.matches(character, ignore_case) => {
+                matched.push(offset..offset + character.len_utf8());
+                t += 1;
+                c += 1;
+            }
+            _ => {
+                let (star_t, star_c, recorded) = backtrack?;
+                t = star_t;
+                c = star_c + 1;
+                matched.truncate(recorded);
+                backtrack = Some((star_t, c, recorded));
+            }
+        }
+    }
+    if tokens[t..]
+        .iter()
+        .all(|token| matches!(token, GlobToken::Star))
+    {
+        Some(merge_ranges(matched))
+    } else {
+        None
+    }
+}
+
+fn merge_ranges(ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+impl App {
+    /// Splits entries of the current directory into those the filter shows
+    /// and those it hides.
+    fn split_by_filter(&self, files: Vec<File>) -> (Vec<File>, Vec<File>) {
+        match &self.filter {
+            Some(filter) => files.into_iter().partition(|file| filter.matches(file)),
+            None => (files, Vec::new()),
+        }
+    }
+
+    /// Merges a sorted batch into the current listing, setting aside what
+    /// the filter hides.
+    pub fn merge_into_current(&mut self, batch: Vec<File>) {
+        let (visible, hidden) = self.split_by_filter(batch);
+        let order = self.sort_order(&self.current_directory);
+        merge_sorted(
+            &mut self.current_directory_contents,
+            visible,
+            &order,
+            &self.current_directory,
+        );
+        merge_sorted(
+            &mut self.filtered_out,
+            hidden,
+            &order,
+            &self.current_directory,
+        );
+    }
+
+    /// Moves entries that don't match the filter out of the current listing.
+    pub fn apply_filter(&mut self) {
+        if self.filter.is_some() {
+            let files = std::mem::take(&mut self.current_directory_contents);
+            self.merge_into_current(files);
+        }
+    }
+
+    /// Puts the entries hidden by the filter back into the current listing.
+    pub fn unfilter(&mut self) {
+        let hidden = std::mem::take(&mut self.filtered_out);
+        let order = self.sort_order(&self.current_directory);
+        merge_sorted(
+            &mut self.current_directory_contents,
+            hidden,
+            &order,
+            &self.current_directory,
+        );
+    }
+
+    /// Applies a changed filter, keeping the cursor on the same file while it
+    /// stays visible.
+    fn refilter(&mut self) {
+        let selected = self.selected_name();
+        self.unfilter();
+        self.apply_filter();
+        if !selected
+            .as_deref()
+            .is_some_and(|name| self.select_by_name(name))
+        {
+            self.clamp_selection();
+        }
+        if self.selected_name() != selected {
+            self.update_selected_item();
+        }
+    }
+
+    pub fn start_filter(&mut self) {
+        self.prompt = Some(Prompt::Filter);
+        if self.filter.is_none() {
+            self.filter = Some(Filter {
+                pattern: String::new(),
+                mode: FilterMode::SmartCase,
+            });
+        }
+    }
+
+    pub fn edit_filter(&mut self, edit: impl FnOnce(&mut Filter)) {
+        if let Some(filter) = &mut self.filter {
+            edit(filter);
+            self.refilter();
+        }
+    }
+
+    /// Stops typing, dropping the filter if nothing was typed.
+    pub fn accept_filter(&mut self) {
+        self.prompt = None;
+        if self
+            .filter
+            .as_ref()
+            .is_some_and(|filter| filter.pattern.is_empty())
+        {
+            self.clear_filter();
+        }
+    }
+
+    pub fn clear_filter(&mut self) {
+        self.prompt = None;
+        self.filter = None;
+        self.refilter();
+    }
+
+    /// Sets the filter for the directory being entered. Sticky filters are
+    /// remembered per directory, otherwise the filter is cleared.
+    pub fn switch_filter(&mut self, to: &std::path::Path) {
+        let filter = self.filter.take();
+        if self.sticky_filters {
+            match filter {
+                Some(filter) => self
+                    .saved_filters
+                    .insert(self.current_directory.clone(), filter),
+                None => self.saved_filters.remove(&self.current_directory),
+            };
+            self.filter = self.saved_filters.get(to).cloned();
+        }
+    }
+}
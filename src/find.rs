@@ -0,0 +1,575 @@
+use std::ffi::OsString;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::file::{File, FileType};
+use crate::filter::{Filter, FilterMode};
+use crate::gitignore::walk_tree;
+use crate::info::InfoType;
+use crate::metadata::{group_id, user_id};
+use crate::virtual_listing::{send_in_batches, VirtualEntry};
+use crate::{App, Prompt};
+
+#[derive(Debug, Clone, Copy)]
+enum Comparison {
+    Less,
+    Equal,
+    Greater,
+}
+
+impl Comparison {
+    fn holds<T: PartialOrd>(self, value: T, reference: T) -> bool {
+        match self {
+            Comparison::Less => value < reference,
+            Comparison::Equal => value == reference,
+            Comparison::Greater => value > reference,
+        }
+    }
+}
+
+enum Predicate {
+    Size(Comparison, u64),
+    /// Compares how long ago the entry was modified.
+    Age(Comparison, Duration),
+    Type(InfoType),
+    Owner(u32),
+    Group(u32),
+    /// Exact permission bits, like `find -perm 755`.
+    Permissions(u32),
+    WorldWritable,
+    Executable,
+    Empty,
+    BrokenSymlink,
+    Name(Filter),
+}
+
+/// A find expression, e.g. `size>1G and mtime<3d or (type=video not empty)`.
+/// Predicates next to each other are combined with and, which binds
+/// tighter than or.
+enum Query {
+    Predicate(Predicate),
+    Not(Box<Query>),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+}
+
+impl Query {
+    fn parse(input: &str) -> Result<Self> {
+        let tokens = tokenize(input);
+        let mut parser = Parser { tokens, next: 0 };
+        let query = parser.or()?;
+        match parser.peek() {
+            None => Ok(query),
+            Some(token) => Err(anyhow!("Unexpected {token:?}")),
+        }
+    }
+
+    fn matches(&self, file: &File, root: &Path, now: SystemTime) -> bool {
+        match self {
+            Query::Predicate(predicate) => predicate.matches(file, root, now),
+            Query::Not(query) => !query.matches(file, root, now),
+            Query::And(a, b) => a.matches(file, root, now) && b.matches(file, root, now),
+            Query::Or(a, b) => a.matches(file, root, now) || b.matches(file, root, now),
+        }
+    }
+}
+
+impl Predicate {
+    fn matches(&self, file: &File, root: &Path, now: SystemTime) -> bool {
+        let Some(metadata) = file.metadata(root) else {
+            return false;
+        };
+        let mode = metadata.permissions().mode();
+        match self {
+            Predicate::Size(comparison, size) => comparison.holds(metadata.len(), *size),
+            Predicate::Age(comparison, age) => metadata
+                .modified()
+                .ok()
+                .and_then(|mtime| now.duration_since(mtime).ok())
+                .is_some_and(|modified_ago| comparison.holds(modified_ago, *age)),
+            Predicate::Type(info_type) => file.info_type(root) == *info_type,
+            Predicate::Owner(uid) => metadata.uid() == *uid,
+            Predicate::Group(gid) => metadata.gid() == *gid,
+            Predicate::Permissions(bits) => mode & 0o7777 == *bits,
+            Predicate::WorldWritable => !metadata.is_symlink() && mode & 0o002 != 0,
+            Predicate::Executable => metadata.is_file() && mode & 0o111 != 0,
+            Predicate::Empty => match file.ftype {
+                FileType::Directory => file.entry_count(root) == Some(0),
+                _ => metadata.is_file() && metadata.len() == 0,
+            },
+            Predicate::BrokenSymlink => {
+                metadata.is_symlink() && std::fs::metadata(root.join(&file.name)).is_err()
+            }
+            Predicate::Name(filter) => Path::new(&file.name)
+                .file_name()
+                .is_some_and(|name| filter.find(&name.to_string_lossy()).is_some()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Operator(char),
+    Open,
+    Close,
+}
+
+fn tokenize(input: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    for c in input.chars() {
+        let token = match c {
+            '(' => Some(Token::Open),
+            ')' => Some(Token::Close),
+            '<' | '>' | '=' => Some(Token::Operator(c)),
+            c if c.is_whitespace() => None,
+            c => {
+                word.push(c);
+                continue;
+            }
+        };
+        if !word.is_empty() {
+            tokens.push(Token::Word(std::mem::take(&mut word)));
+        }
+        tokens.extend(token);
+    }
+    if !word.is_empty() {
+        tokens.push(Token::Word(word));
+    }
+    tokens
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    fn advance(&mut self) -> Option<&Token> {
+        self.next += 1;
+        self.tokens.get(self.next - 1)
+    }
+
+    fn peek_word(&self, expected: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word == expected)
+    }
+
+    fn or(&mut self) -> Result<Query> {
+        let mut query = self.and()?;
+        while self.peek_word("or") {
+            self.advance();
+            query = Query::Or(Box::new(query), Box::new(self.and()?));
+        }
+        Ok(query)
+    }
+
+    fn and(&mut self) -> Result<Query> {
+        let mut query = self.unary()?;
+        loop {
+            match self.peek() {
+                Some(Token::Word(word)) if word == "and" => {
+                    self.advance();
+                }
+                Some(Token::Word(word)) if word != "or" => (),
+                Some(Token::Open) => (),
+                _ => return Ok(query),
+            }
+            query = Query::And(Box::new(query), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Query> {
+        match self.advance() {
+            Some(Token::Word(word)) if word == "not" => Ok(Query::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let query = self.or()?;
+                match self.advance() {
+                    Some(Token::Close) => Ok(query),
+                    _ => Err(anyhow!("Missing )")),
+                }
+            }
+            Some(Token::Word(word)) => {
+                let word = word.clone();
+                self.predicate(&word).map(Query::Predicate)
+            }
+            Some(token) => Err(anyhow!("Unexpected {token:?}")),
+            None => Err(anyhow!("Incomplete query")),
+        }
+    }
+
+    fn predicate(&mut self, key: &str) -> Result<Predicate> {
+        match key {
+            "empty" => return Ok(Predicate::Empty),
+            "broken" => return Ok(Predicate::BrokenSymlink),
+            "world-writable" => return Ok(Predicate::WorldWritable),
+            "executable" => return Ok(Predicate::Executable),
+            _ => (),
+        }
+        let comparison = match self.advance() {
+            Some(Token::Operator('<')) => Comparison::Less,
+            Some(Token::Operator('=')) => Comparison::Equal,
+            Some(Token::Operator('>')) => Comparison::Greater,
+            _ => bail!("Expected <, = or > after {key}"),
+        };
+        let value = match self.advance() {
+            Some(Token::Word(value)) => value.clone(),
+            _ => bail!("Expected a value for {key}"),
+        };
+        let equal = |predicate: Predicate| match comparison {
+            Comparison::Equal => Ok(predicate),
+            _ => Err(anyhow!("{key} can only be compared with =")),
+        };
+        match key {
+            "size" => Ok(Predicate::Size(comparison, parse_size(&value)?)),
+            "mtime" | "age" => Ok(Predicate::Age(comparison, parse_age(&value)?)),
+            "type" => equal(Predicate::Type(parse_type(&value)?)),
+            "owner" | "user" => equal(Predicate::Owner(
+                value
+                    .parse()
+                    .ok()
+                    .or_else(|| user_id(&value))
+                    .ok_or(anyhow!("Unknown user {value}"))?,
+            )),
+            "group" => equal(Predicate::Group(
+                value
+                    .parse()
+                    .ok()
+                    .or_else(|| group_id(&value))
+                    .ok_or(anyhow!("Unknown group {value}"))?,
+            )),
+            "perm" => equal(Predicate::Permissions(
+                u32::from_str_radix(&value, 8).map_err(|_| anyhow!("Bad permissions {value}"))?,
+            )),
+            "name" => equal(Predicate::Name(Filter {
+                pattern: value,
+                mode: FilterMode::Glob,
+            })),
+            _ => Err(anyhow!("Unknown predicate {key}")),
+        }
+    }
+}
+
+/// Bytes, with an optional K, M, G or T suffix in powers of 1024.
+fn parse_size(value: &str) -> Result<u64> {
+    let (number, unit) = split_unit(value);
+    let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => bail!("Unknown size unit {unit}"),
+    };
+    let number: f64 = number.parse().map_err(|_| anyhow!("Bad size {value}"))?;
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// A duration like `3d`, with s, m, h, d, w or y as the unit.
+fn parse_age(value: &str) -> Result<Duration> {
+    let (number, unit) = split_unit(value);
+    let seconds: f64 = match unit {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "" | "d" => 86400.0,
+        "w" => 7.0 * 86400.0,
+        "y" => 365.0 * 86400.0,
+        _ => bail!("Unknown age unit {unit}"),
+    };
+    let number: f64 = number.parse().map_err(|_| anyhow!("Bad age {value}"))?;
+    Ok(Duration::from_secs_f64(number * seconds))
+}
+
+fn split_unit(value: &str) -> (&str, &str) {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    value.split_at(split)
+}
+
+fn parse_type(value: &str) -> Result<InfoType> {
+    Ok(match value.to_lowercase().as_str() {
+        "text" => InfoType::Text,
+        "executable" => InfoType::Executable,
+        "image" => InfoType::Image,
+        "video" => InfoType::Video,
+        "audio" => InfoType::Audio,
+        "pdf" => InfoType::Pdf,
+        "link" => InfoType::Link,
+        "directory" | "dir" => InfoType::Directory,
+        "script" | "shellscript" => InfoType::ShellScript,
+        "unknown" => InfoType::Unknown,
+        _ => bail!("Unknown type {value}"),
+    })
+}
+
+/// Sends the entries below `root` that match `query` in batches.
+fn find(
+    root: PathBuf,
+    query: Query,
+    show_hidden: bool,
+    respect_gitignore: bool,
+    sender: Sender<Vec<VirtualEntry>>,
+    cancelled: Arc<AtomicBool>,
+) {
+    let now = SystemTime::now();
+    let entries = walk_tree(&root, show_hidden, respect_gitignore)
+        .take_while(|_| !cancelled.load(Ordering::Relaxed))
+        .filter_map(|entry| {
+            let name = OsString::from(entry.path().strip_prefix(&root).unwrap_or(entry.path()));
+            let file = File::new(FileType::from(entry.file_type()), name);
+            query.matches(&file, &root, now).then_some(VirtualEntry {
+                file,
+                preview: None,
+            })
+        });
+    send_in_batches(entries, &sender);
+}
+
+impl App {
+    pub fn start_find(&mut self) {
+        self.prompt = Some(Prompt::Find);
+        self.find_query.clear();
+    }
+
+    pub fn run_find(&mut self) -> Result<()> {
+        self.prompt = None;
+        if self.find_query.trim().is_empty() {
+            return Ok(());
+        }
+        let query = Query::parse(&self.find_query)?;
+        let root = self.current_directory.clone();
+        let (show_hidden, respect_gitignore) = (self.show_hidden, self.respect_gitignore);
+        self.start_virtual_listing(
+            format!("find: {}", self.find_query),
+            None,
+            move |sender, cancelled| {
+                find(
+                    root,
+                    query,
+                    show_hidden,
+                    respect_gitignore,
+                    sender,
+                    cancelled,
+                )
+            },
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// The structure of a query, with its grouping spelled out.
+    fn shape(query: &Query) -> String {
+        match query {
+            Query::Predicate(predicate) => String::from(match predicate {
+                Predicate::Size(..) => "size",
+                Predicate::Age(..) => "mtime",
+                Predicate::Type(_) => "type",
+                Predicate::Owner(_) => "owner",
+                Predicate::Group(_) => "group",
+                Predicate::Permissions(_) => "perm",
+                Predicate::WorldWritable => "world-writable",
+                Predicate::Executable => "executable",
+                Predicate::Empty => "empty",
+                Predicate::BrokenSymlink => "broken",
+                Predicate::Name(_) => "name",
+            }),
+            Query::Not(query) => format!("not {}", shape(query)),
+            Query::And(a, b) => format!("({} and {})", shape(a), shape(b)),
+            Query::Or(a, b) => format!("({} or {})", shape(a), shape(b)),
+        }
+    }
+
+    fn parsed(input: &str) -> String {
+        shape(&Query::parse(input).unwrap())
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            parsed("size>1G and mtime<3d or empty"),
+            "((size and mtime) or empty)"
+        );
+        assert_eq!(
+            parsed("empty or size>1G mtime<3d"),
+            "(empty or (size and mtime))"
+        );
+        assert_eq!(
+            parsed("empty or broken or executable"),
+            "((empty or broken) or executable)"
+        );
+    }
+
+    #[test]
+    fn not_applies_to_the_next_predicate() {
+        assert_eq!(parsed("not empty executable"), "(not empty and executable)");
+        assert_eq!(parsed("not not empty"), "not not empty");
+    }
+
+    #[test]
+    fn parentheses_group() {
+        assert_eq!(
+            parsed("size>1G and (mtime<3d or empty)"),
+            "(size and (mtime or empty))"
+        );
+        assert_eq!(
+            parsed("not (type=video or type=audio)"),
+            "not (type or type)"
+        );
+        assert_eq!(parsed("(empty)(broken)"), "(empty and broken)");
+    }
+
+    #[test]
+    fn rejects_malformed_queries() {
+        for input in [
+            "(empty",
+            "empty)",
+            "size",
+            "size>",
+            "size>1X",
+            "mtime<3q",
+            "type>text",
+            "type=sock",
+            "perm=9",
+            "colour=red",
+            "empty or",
+            "not",
+        ] {
+            assert!(Query::parse(input).is_err(), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("0").unwrap(), 0);
+        assert_eq!(parse_size("10").unwrap(), 10);
+        assert_eq!(parse_size("10b").unwrap(), 10);
+        assert_eq!(parse_size("2K").unwrap(), 2048);
+        assert_eq!(parse_size("1.5m").unwrap(), 3 << 19);
+        assert_eq!(parse_size("1G").unwrap(), 1 << 30);
+        assert_eq!(parse_size("1T").unwrap(), 1 << 40);
+        assert!(parse_size("K").is_err());
+        assert!(parse_size("1KB").is_err());
+    }
+
+    #[test]
+    fn parses_ages() {
+        assert_eq!(parse_age("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_age("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_age("3").unwrap(), Duration::from_secs(3 * 86400));
+        assert_eq!(parse_age("1w").unwrap(), Duration::from_secs(7 * 86400));
+        assert!(parse_age("3M").is_err());
+    }
+
+    /// A directory with a few entries to match against, removed on drop.
+    struct Fixture(PathBuf);
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("kranger-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&root);
+            std::fs::create_dir_all(root.join("empty_dir")).unwrap();
+            std::fs::create_dir_all(root.join("full_dir")).unwrap();
+            std::fs::write(root.join("full_dir/inner.txt"), "x").unwrap();
+            std::fs::write(root.join("big.txt"), vec![b'x'; 4096]).unwrap();
+            std::fs::write(root.join("empty.txt"), "").unwrap();
+            std::fs::write(root.join("run.sh"), "#!/bin/sh\n").unwrap();
+            let mode = |path: &str, mode| {
+                std::fs::set_permissions(root.join(path), std::fs::Permissions::from_mode(mode))
+                    .unwrap()
+            };
+            mode("run.sh", 0o755);
+            mode("big.txt", 0o644);
+            mode("empty.txt", 0o666);
+            symlink(root.join("missing"), root.join("dangling")).unwrap();
+            Fixture(root)
+        }
+
+        /// The entries `input` matches, as if they were modified `ago`.
+        fn find(&self, input: &str, ago: Duration) -> Vec<String> {
+            let query = Query::parse(input).unwrap();
+            let now = SystemTime::now() + ago;
+            let mut names: Vec<String> = std::fs::read_dir(&self.0)
+                .unwrap()
+                .flatten()
+                .map(|entry| {
+                    let ftype = entry.file_type().map(FileType::from).unwrap();
+                    File::new(ftype, entry.file_name())
+                })
+                .filter(|file| query.matches(file, &self.0, now))
+                .map(|file| file.name.to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn matches_predicates() {
+        let fixture = Fixture::new("predicates");
+        let now = Duration::ZERO;
+        assert_eq!(fixture.find("size>1K type=text", now), ["big.txt"]);
+        assert_eq!(fixture.find("size=0 not type=dir", now), ["empty.txt"]);
+        assert_eq!(fixture.find("empty", now), ["empty.txt", "empty_dir"]);
+        assert_eq!(fixture.find("perm=755 not type=dir", now), ["run.sh"]);
+        assert_eq!(fixture.find("executable", now), ["run.sh"]);
+        assert_eq!(fixture.find("broken", now), ["dangling"]);
+        assert_eq!(fixture.find("name=*.txt", now), ["big.txt", "empty.txt"]);
+    }
+
+    #[test]
+    fn matches_combinations() {
+        let fixture = Fixture::new("combinations");
+        let now = Duration::ZERO;
+        assert_eq!(
+            fixture.find("name=*.txt and size>1K or executable", now),
+            ["big.txt", "run.sh"]
+        );
+        assert_eq!(
+            fixture.find("name=*.txt (size>1K or empty)", now),
+            ["big.txt", "empty.txt"]
+        );
+        assert_eq!(
+            fixture.find("not (type=dir or broken or name=*.txt)", now),
+            ["run.sh"]
+        );
+    }
+
+    #[test]
+    fn matches_ages() {
+        let fixture = Fixture::new("ages");
+        let files = "not type=dir not broken";
+        let all = ["big.txt", "empty.txt", "run.sh"];
+        assert_eq!(
+            fixture.find(&format!("{files} mtime<1h"), Duration::ZERO),
+            all
+        );
+        let two_days = Duration::from_secs(2 * 86400);
+        assert!(fixture
+            .find(&format!("{files} mtime<1d"), two_days)
+            .is_empty());
+        assert_eq!(fixture.find(&format!("{files} mtime>1d"), two_days), all);
+    }
+}
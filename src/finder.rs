@@ -0,0 +1,330 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::case::smart_case;
+use crate::fuzzy::fuzzy_match;
+use crate::gitignore::walk_tree;
+use crate::sanitize::{escape_invalid_utf8, sanitize_name};
+use crate::{App, Prompt};
+
+const BATCH_SIZE: usize = 1024;
+const BATCH_INTERVAL: Duration = Duration::from_millis(50);
+/// Only this many of the best matches are kept in order.
+const MAX_RESULTS: usize = 1000;
+/// The walk stops after this many entries.
+const MAX_CANDIDATES: usize = 1 << 20;
+
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    /// Relative to the directory the finder was started in.
+    pub path: PathBuf,
+    pub display: String,
+    pub is_dir: bool,
+}
+
+/// What the scoring thread is told.
+enum Message {
+    Found(Vec<Candidate>),
+    /// The walk is over, and whether it got through everything.
+    Walked(bool),
+    Query(String),
+}
+
+/// The best matches of a query, as scored so far.
+struct Scored {
+    query: String,
+    results: Vec<Candidate>,
+    match_count: usize,
+    candidate_count: usize,
+    walking: bool,
+    complete: bool,
+}
+
+/// Fuzzy finder over everything below a directory. The directory is walked
+/// on one background thread and the candidates are scored on another, which
+/// keeps them all, so typing never waits for a rescore. Dropping the finder
+/// stops both.
+pub struct Finder {
+    pub root: PathBuf,
+    pub query: String,
+    /// The best matches, best first, and the query they matched.
+    pub results: Vec<Candidate>,
+    pub results_query: String,
+    pub selection: usize,
+    match_count: usize,
+    candidate_count: usize,
+    walking: bool,
+    complete: bool,
+    messages: Sender<Message>,
+    receiver: Receiver<Scored>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Finder {
+    pub fn new(root: &Path, show_hidden: bool, respect_gitignore: bool) -> Self {
+        let (messages, scorer_messages) = channel();
+        let (scorer_sender, receiver) = channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let walk_cancelled = Arc::clone(&cancelled);
+        let walk_messages = messages.clone();
+        let walk_root = root.to_path_buf();
+        std::thread::spawn(move || {
+            walk(
+                &walk_root,
+                show_hidden,
+                respect_gitignore,
+                walk_messages,
+                walk_cancelled,
+            )
+        });
+        let scorer_cancelled = Arc::clone(&cancelled);
+        std::thread::spawn(move || score(scorer_messages, scorer_sender, scorer_cancelled));
+
+        Self {
+            root: root.to_path_buf(),
+            query: String::new(),
+            results: Vec::new(),
+            results_query: String::new(),
+            selection: 0,
+            match_count: 0,
+            candidate_count: 0,
+            walking: true,
+            complete: true,
+            messages,
+            receiver,
+            cancelled,
+        }
+    }
+
+    pub fn is_walking(&self) -> bool {
+        self.walking
+    }
+
+    /// Whether the walk stopped at the candidate limit.
+    pub fn is_incomplete(&self) -> bool {
+        !self.complete
+    }
+
+    pub fn match_count(&self) -> usize {
+        self.match_count
+    }
+
+    pub fn candidate_count(&self) -> usize {
+        self.candidate_count
+    }
+
+    /// Takes the latest results of the scoring thread.
+    pub fn receive(&mut self) {
+        let Some(scored) = self.receiver.try_iter().last() else {
+            return;
+        };
+        self.results = scored.results;
+        self.results_query = scored.query;
+        self.match_count = scored.match_count;
+        self.candidate_count = scored.candidate_count;
+        self.walking = scored.walking;
+        self.complete = scored.complete;
+        self.selection = self.selection.min(self.results.len().saturating_sub(1));
+    }
+
+    pub fn set_query(&mut self, query: String) {
+        self.query = query.clone();
+        self.selection = 0;
+        let _ = self.messages.send(Message::Query(query));
+    }
+
+    pub fn change_selection(&mut self, change_by: isize) {
+        self.selection = self
+            .selection
+            .saturating_add_signed(change_by)
+            .min(self.results.len().saturating_sub(1));
+    }
+
+    pub fn selected(&self) -> Option<&Candidate> {
+        self.results.get(self.selection)
+    }
+}
+
+impl Drop for Finder {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+fn walk(
+    root: &Path,
+    show_hidden: bool,
+    respect_gitignore: bool,
+    messages: Sender<Message>,
+    cancelled: Arc<AtomicBool>,
+) {
+    let entries = walk_tree(root, show_hidden, respect_gitignore);
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut last_sent = Instant::now();
+    for (found, entry) in entries.enumerate() {
+        if cancelled.load(Ordering::Relaxed) {
+            return;
+        }
+        if found == MAX_CANDIDATES {
+            let _ = messages.send(Message::Found(batch));
+            let _ = messages.send(Message::Walked(false));
+            return;
+        }
+        let path = entry
+            .path()
+            .strip_prefix(root)
+            .unwrap_or(entry.path())
+            .to_path_buf();
+        batch.push(Candidate {
+            display: sanitize_name(&escape_invalid_utf8(path.as_os_str())),
+            path,
+            is_dir: entry.file_type().is_dir(),
+        });
+        if batch.len() >= BATCH_SIZE || last_sent.elapsed() >= BATCH_INTERVAL {
+            if messages
+                .send(Message::Found(std::mem::take(&mut batch)))
+                .is_err()
+            {
+                return;
+            }
+            last_sent = Instant::now();
+        }
+    }
+    let _ = messages.send(Message::Found(batch));
+    let _ = messages.send(Message::Walked(true));
+}
+
+/// Keeps every candidate with its score for the current query, and sends
+/// the best ones whenever either changes. Queries typed while a rescore
+/// runs are picked up together, only the last one is scored.
+fn score(messages: Receiver<Message>, sender: Sender<Scored>, cancelled: Arc<AtomicBool>) {
+    let mut candidates: Vec<Candidate> = Vec::new();
+    // Score and index of every candidate matching the query.
+    let mut matches: Vec<(i64, usize)> = Vec::new();
+    let mut query = String::new();
+    let mut walking = true;
+    let mut complete = true;
+    while let Ok(message) = messages.recv() {
+        let scored = candidates.len();
+        let mut new_query = None;
+        for message in std::iter::once(message).chain(messages.try_iter()) {
+            match message {
+                Message::Found(batch) => candidates.extend(batch),
+                Message::Walked(all) => {
+                    walking = false;
+                    complete = all;
+                }
+                Message::Query(query) => new_query = Some(query),
+            }
+        }
+        if let Some(new_query) = new_query {
+            // Adding to the end of the query can only narrow the matches.
+            let narrowing =
+                new_query.starts_with(&query) && smart_case(&new_query) == smart_case(&query);
+            let indices: Vec<usize> = match narrowing {
+                true => matches.iter().map(|(_, index)| *index).collect(),
+                false => (0..scored).collect(),
+            };
+            query = new_query;
+            matches = indices
+                .into_iter()
+                .filter_map(|index| {
+                    fuzzy_match(&candidates[index].display, &query).map(|(score, _)| (score, index))
+                })
+                .collect();
+        }
+        for (index, candidate) in candidates.iter().enumerate().skip(scored) {
+            if let Some((score, _)) = fuzzy_match(&candidate.display, &query) {
+                matches.push((score, index));
+            }
+        }
+        if cancelled.load(Ordering::Relaxed) {
+            return;
+        }
+        let scored = Scored {
+            query: query.clone(),
+            results: best(&candidates, &matches),
+            match_count: matches.len(),
+            candidate_count: candidates.len(),
+            walking,
+            complete,
+        };
+        if sender.send(scored).is_err() {
+            return;
+        }
+    }
+}
+
+/// Best score first, then shorter paths, then the order they were found.
+fn best(candidates: &[Candidate], matches: &[(i64, usize)]) -> Vec<Candidate> {
+    let key = |(score, index): &(i64, usize)| (-score, candidates[*index].display.len(), *index);
+    let mut best = matches.to_vec();
+    if best.len() > MAX_RESULTS {
+        best.select_nth_unstable_by_key(MAX_RESULTS, key);
+        best.truncate(MAX_RESULTS);
+    }
+    best.sort_unstable_by_key(key);
+    best.into_iter()
+        .map(|(_, index)| candidates[index].clone())
+        .collect()
+}
+
+impl App {
+    pub fn start_finder(&mut self) {
+        self.prompt = Some(Prompt::Finder);
+        self.finder = Some(Finder::new(
+            &self.current_directory,
+            self.show_hidden,
+            self.respect_gitignore,
+        ));
+    }
+
+    pub fn edit_finder_query(&mut self, edit: impl FnOnce(&mut String)) {
+        if let Some(finder) = &mut self.finder {
+            let mut query = finder.query.clone();
+            edit(&mut query);
+            finder.set_query(query);
+        }
+    }
+
+    /// Walks again after a setting changed, keeping the query.
+    pub fn restart_finder(&mut self) {
+        if let Some(finder) = self.finder.take() {
+            let mut restarted = Finder::new(&finder.root, self.show_hidden, self.respect_gitignore);
+            restarted.set_query(finder.query.clone());
+            self.finder = Some(restarted);
+        }
+    }
+
+    pub fn receive_finder_results(&mut self) {
+        if let Some(finder) = &mut self.finder {
+            finder.receive();
+        }
+    }
+
+    pub fn close_finder(&mut self) {
+        self.prompt = None;
+        self.finder = None;
+    }
+
+    /// Goes to the directory holding the selected result, with the cursor
+    /// on it.
+    pub fn accept_finder(&mut self) {
+        let picked = self
+            .finder
+            .as_ref()
+            .and_then(|finder| Some(finder.root.join(&finder.selected()?.path)));
+        self.close_finder();
+        let Some(picked) = picked else {
+            return;
+        };
+        if let (Some(directory), Some(name)) = (picked.parent(), picked.file_name()) {
+            self.pending_selection = Some(OsString::from(name));
+            self.change_directory(directory.to_path_buf());
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::ffi::OsString;
+use std::sync::atomic::Ordering;
+
+use crate::file::{File, FileType};
+use crate::gitignore::walk_tree_to_depth;
+use crate::virtual_listing::{send_in_batches, VirtualEntry};
+use crate::App;
+
+pub const DEFAULT_FLATTEN_DEPTH: usize = 4;
+
+impl App {
+    /// Lists everything below the current directory at once, with paths
+    /// relative to it. Stays on while navigating until toggled off.
+    pub fn toggle_flatten(&mut self) {
+        if self.flatten {
+            self.flatten = false;
+            self.close_virtual_listing();
+        } else {
+            self.start_flatten();
+        }
+    }
+
+    pub fn toggle_flatten_files_only(&mut self) {
+        self.flatten_files_only = !self.flatten_files_only;
+        if self.flatten {
+            self.start_flatten();
+        }
+    }
+
+    pub fn change_flatten_depth(&mut self, change_by: isize) {
+        self.flatten_depth = self.flatten_depth.saturating_add_signed(change_by).max(1);
+        self.msg(format!("Flattening {} levels deep", self.flatten_depth));
+        if self.flatten {
+            self.start_flatten();
+        }
+    }
+
+    pub fn start_flatten(&mut self) {
+        let root = self.current_directory.clone();
+        let (depth, files_only) = (self.flatten_depth, self.flatten_files_only);
+        let (show_hidden, hide_ignored) = (self.show_hidden, self.hide_ignored);
+        let title = format!(
+            "flattened, {depth} deep{}",
+            if files_only { ", files only" } else { "" }
+        );
+        self.start_virtual_listing(title, None, move |sender, cancelled| {
+            let entries = walk_tree_to_depth(&root, depth, show_hidden, hide_ignored)
+                .take_while(|_| !cancelled.load(Ordering::Relaxed))
+                .filter(|entry| !(files_only && entry.file_type().is_dir()))
+                .map(|entry| {
+                    let name =
+                        OsString::from(entry.path().strip_prefix(&root).unwrap_or(entry.path()));
+                    VirtualEntry {
+                        file: File::new(FileType::from(entry.file_type()), name),
+                        preview: None,
+                    }
+                });
+            send_in_batches(entries, &sender);
+        });
+        self.flatten = true;
+    }
+}
@@ -0,0 +1,103 @@
+use crate::case::{chars_equal, smart_case};
+
+// Scoring in the spirit of fzf: matched characters score more at word and
+// path boundaries and in consecutive runs, gaps cost a little.
+const SCORE_MATCH: i64 = 16;
+const PENALTY_GAP_START: i64 = -3;
+const PENALTY_GAP_EXTENSION: i64 = -1;
+const BONUS_PATH_SEPARATOR: i64 = 10;
+const BONUS_BOUNDARY: i64 = 8;
+const BONUS_CAMEL_CASE: i64 = 7;
+const BONUS_CONSECUTIVE: i64 = 4;
+const BONUS_FILE_NAME: i64 = 2;
+
+fn bonus(previous: Option<char>, current: char) -> i64 {
+    match previous {
+        None => BONUS_BOUNDARY,
+        Some('/') => BONUS_PATH_SEPARATOR,
+        Some(previous) if !previous.is_alphanumeric() => BONUS_BOUNDARY,
+        Some(previous) if previous.is_lowercase() && current.is_uppercase() => BONUS_CAMEL_CASE,
+        _ => 0,
+    }
+}
+
+/// Matches `pattern` as a subsequence of `text`. Returns the score, higher
+/// is better, and the byte offsets of the matched characters.
+pub fn fuzzy_match(text: &str, pattern: &str) -> Option<(i64, Vec<usize>)> {
+    let ignore_case = smart_case(pattern);
+    let pattern: Vec<char> = pattern.chars().collect();
+    if pattern.is_empty() {
+        return Some((0, Vec::new()));
+    }
+    let text: Vec<(usize, char)> = text.char_indices().collect();
+
+    // The first complete match going forward, then the latest start that
+    // still matches up to the same end, which gives the tightest window.
+    let mut p = 0;
+    let mut end = None;
+    for (index, (_, c)) in text.iter().enumerate() {
+        if chars_equal(*c, pattern[p], ignore_case) {
+            p += 1;
+            if p == pattern.len() {
+                end = Some(index);
+                break;
+            }
+        }
+    }
+    let end = end?;
+    let mut p = pattern.len();
+    let mut start = end;
+    for index in (0..=end).rev() {
+        if chars_equal(text[index].1, pattern[p - 1], ignore_case) {
+            p -= 1;
+            if p == 0 {
+                start = index;
+                break;
+            }
+        }
+    }
+
+    let file_name_start = text
+        .iter()
+        .rposition(|(_, c)| *c == '/')
+        .map_or(0, |index| index + 1);
+    let mut score = 0;
+    let mut positions = Vec::with_capacity(pattern.len());
+    let mut p = 0;
+    let mut previous_match: Option<usize> = None;
+    let mut run_bonus = 0;
+    for index in start..=end {
+        if p == pattern.len() {
+            break;
+        }
+        let (offset, c) = text[index];
+        if !chars_equal(c, pattern[p], ignore_case) {
+            continue;
+        }
+        let mut char_bonus = bonus(index.checked_sub(1).map(|i| text[i].1), c);
+        match previous_match {
+            Some(previous) if previous + 1 == index => {
+                // A run keeps the bonus of the boundary it started at.
+                run_bonus = run_bonus.max(char_bonus).max(BONUS_CONSECUTIVE);
+                char_bonus = run_bonus;
+            }
+            Some(previous) => {
+                let gap = (index - previous - 1) as i64;
+                score += PENALTY_GAP_START + PENALTY_GAP_EXTENSION * (gap - 1);
+                run_bonus = char_bonus;
+            }
+            None => {
+                char_bonus *= 2;
+                run_bonus = char_bonus;
+            }
+        }
+        if index >= file_name_start {
+            char_bonus += BONUS_FILE_NAME;
+        }
+        score += SCORE_MATCH + char_bonus;
+        positions.push(offset);
+        previous_match = Some(index);
+        p += 1;
+    }
+    Some((score, positions))
+}
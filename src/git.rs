@@ -0,0 +1,457 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::hash::{Hash, Hasher};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{channel, TryRecvError};
+
+use crate::ansi;
+use crate::file::FileType;
+use crate::info::{Info, InfoType};
+use crate::sanitize::{sanitize_name, sanitize_text};
+use crate::App;
+
+/// The status of an entry, ordered so that the more important one wins when
+/// a directory holds several.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GitState {
+    Ignored,
+    Untracked,
+    Staged,
+    Modified,
+    Conflicted,
+}
+
+impl GitState {
+    /// A colored, two columns wide marker.
+    pub fn marker(self) -> String {
+        let (color, symbol) = match self {
+            GitState::Ignored => (ansi::GRAY, '!'),
+            GitState::Untracked => (ansi::MAGENTA, '?'),
+            GitState::Staged => (ansi::GREEN, '+'),
+            GitState::Modified => (ansi::YELLOW, '*'),
+            GitState::Conflicted => (ansi::RED, 'C'),
+        };
+        format!("{color}{symbol}{} ", ansi::RESET)
+    }
+}
+
+/// What `git status` says about a work tree.
+pub struct GitStatus {
+    pub root: PathBuf,
+    /// Where git keeps the repository, which is elsewhere for worktrees and
+    /// submodules.
+    pub git_dir: PathBuf,
+    pub branch: String,
+    pub ahead: u32,
+    pub behind: u32,
+    files: HashMap<PathBuf, GitState>,
+    /// Directories containing changes, with the most important one.
+    dirty_directories: HashMap<PathBuf, GitState>,
+}
+
+impl GitStatus {
+    pub fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.root)
+    }
+
+    pub fn state(&self, path: &Path) -> Option<GitState> {
+        if let Some(state) = self.files.get(path) {
+            return Some(*state);
+        }
+        if let Some(state) = self.dirty_directories.get(path) {
+            return Some(*state);
+        }
+        // Untracked and ignored directories are listed as a whole.
+        path.ancestors()
+            .skip(1)
+            .take_while(|ancestor| ancestor.starts_with(&self.root))
+            .find_map(|ancestor| {
+                self.files
+                    .get(ancestor)
+                    .filter(|state| **state <= GitState::Untracked)
+            })
+            .copied()
+    }
+
+    /// Parses `git status --porcelain=v2 --branch --ignored -z`.
+    fn parse(root: PathBuf, output: &[u8]) -> Self {
+        let mut status = GitStatus {
+            git_dir: root.join(".git"),
+            root,
+            branch: String::new(),
+            ahead: 0,
+            behind: 0,
+            files: HashMap::new(),
+            dirty_directories: HashMap::new(),
+        };
+        let mut records = output.split(|byte| *byte == 0);
+        while let Some(record) = records.next() {
+            // Paths are kept as the bytes git prints, they need not be UTF-8.
+            let Some((kind, rest)) = split_field(record) else {
+                continue;
+            };
+            let (state, path) = match kind {
+                b"#" => {
+                    status.parse_header(&String::from_utf8_lossy(rest));
+                    continue;
+                }
+                b"?" => (GitState::Untracked, rest),
+                b"!" => (GitState::Ignored, rest),
+                b"1" | b"2" | b"u" => {
+                    // Changed entries have 7 fields before the path, renames
+                    // 8 and unmerged ones 9. Renames are followed by the
+                    // original path.
+                    let skip = match kind {
+                        b"1" => 7,
+                        b"2" => 8,
+                        _ => 9,
+                    };
+                    let Some(path) = rest.splitn(skip + 1, |byte| *byte == b' ').nth(skip) else {
+                        continue;
+                    };
+                    if kind == b"2" {
+                        records.next();
+                    }
+                    let state = match (kind, rest.get(1)) {
+                        (b"u", _) => GitState::Conflicted,
+                        (_, Some(b'.')) => GitState::Staged,
+                        _ => GitState::Modified,
+                    };
+                    (state, path)
+                }
+                _ => continue,
+            };
+            let path = path.strip_suffix(b"/").unwrap_or(path);
+            let path = status.root.join(OsStr::from_bytes(path));
+            status.mark(path, state);
+        }
+        status
+    }
+
+    fn parse_header(&mut self, header: &str) {
+        if let Some(branch) = header.strip_prefix("branch.head ") {
+            self.branch = branch.to_owned();
+        } else if let Some(counts) = header.strip_prefix("branch.ab ") {
+            for count in counts.split(' ') {
+                if let Some(ahead) = count.strip_prefix('+') {
+                    self.ahead = ahead.parse().unwrap_or(0);
+                } else if let Some(behind) = count.strip_prefix('-') {
+                    self.behind = behind.parse().unwrap_or(0);
+                }
+            }
+        }
+    }
+
+    /// Records the state of `path` and marks its directories as dirty.
+    fn mark(&mut self, path: PathBuf, state: GitState) {
+        if state != GitState::Ignored {
+            for directory in path.ancestors().skip(1) {
+                if !directory.starts_with(&self.root) {
+                    break;
+                }
+                let dirty = self
+                    .dirty_directories
+                    .entry(directory.to_path_buf())
+                    .or_insert(state);
+                *dirty = (*dirty).max(state);
+            }
+        }
+        self.files.insert(path, state);
+    }
+}
+
+/// The first space separated field of a status record, and the rest.
+fn split_field(record: &[u8]) -> Option<(&[u8], &[u8])> {
+    let space = record.iter().position(|byte| *byte == b' ')?;
+    Some((&record[..space], &record[space + 1..]))
+}
+
+/// Where git keeps the repository of the work tree at `root`. Worktrees and
+/// submodules have a `.git` file pointing to it, read here rather than asking
+/// git so the UI thread doesn't wait on it.
+fn git_dir(root: &Path) -> PathBuf {
+    let dot_git = root.join(".git");
+    std::fs::read(&dot_git)
+        .ok()
+        .and_then(|contents| {
+            let line = contents.split(|byte| *byte == b'\n').next()?;
+            let git_dir = line.strip_prefix(b"gitdir: ")?;
+            Some(root.join(OsStr::from_bytes(git_dir)))
+        })
+        .unwrap_or(dot_git)
+}
+
+/// The work tree holding `directory`, if any.
+pub fn work_tree_root(directory: &Path) -> Option<PathBuf> {
+    directory
+        .ancestors()
+        .find(|ancestor| ancestor.join(".git").exists())
+        .map(Path::to_path_buf)
+}
+
+/// Runs git in `directory`, returning its output if it succeeds.
+fn git<I, S>(directory: &Path, args: I) -> Option<Vec<u8>>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output.status.success().then_some(output.stdout)
+}
+
+fn read_status(root: PathBuf) -> Option<GitStatus> {
+    let output = git(
+        &root,
+        ["status", "--porcelain=v2", "--branch", "--ignored", "-z"],
+    )?;
+    let mut status = GitStatus::parse(root, &output);
+    if let Some(output) = git(&status.root, ["rev-parse", "--git-dir"]) {
+        let git_dir = output.strip_suffix(b"\n").unwrap_or(&output);
+        status.git_dir = status.root.join(OsStr::from_bytes(git_dir));
+    }
+    Some(status)
+}
+
+/// What the preview of a changed or tracked file shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GitView {
+    Diff,
+    Log,
+    Blame,
+}
+
+impl GitView {
+    pub fn next(self) -> Self {
+        match self {
+            GitView::Diff => GitView::Log,
+            GitView::Log => GitView::Blame,
+            GitView::Blame => GitView::Diff,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GitView::Diff => "diff",
+            GitView::Log => "log",
+            GitView::Blame => "blame",
+        }
+    }
+}
+
+/// A preview built by git rather than from the file alone. Carries a
+/// fingerprint of the repository so it is rebuilt after staging, committing
+/// or checking out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GitPreview {
+    File(GitView, u64),
+    /// Branch, dirty counts and recent commits of a repository root.
+    Summary(u64),
+}
+
+const MAX_GIT_LINES: usize = 500;
+const SUMMARY_COMMITS: usize = 10;
+
+fn fingerprint(git_dir: &Path) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for file in ["HEAD", "index", "logs/HEAD"] {
+        let mtime = std::fs::metadata(git_dir.join(file)).and_then(|meta| meta.modified());
+        mtime.ok().hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+fn output_lines(output: &[u8], tab_width: usize) -> Vec<String> {
+    String::from_utf8_lossy(output)
+        .lines()
+        .take(MAX_GIT_LINES)
+        .map(|line| sanitize_text(line, tab_width))
+        .collect()
+}
+
+fn colored(color: &str, text: &str) -> String {
+    format!("{color}{text}{}", ansi::RESET)
+}
+
+fn color_diff_line(line: String) -> String {
+    if line.starts_with("+++") || line.starts_with("---") {
+        line
+    } else if line.starts_with('+') {
+        colored(ansi::GREEN, &line)
+    } else if line.starts_with('-') {
+        colored(ansi::RED, &line)
+    } else if line.starts_with("@@") {
+        colored(ansi::CYAN, &line)
+    } else {
+        line
+    }
+}
+
+/// Colors the abbreviated hash a log or blame line starts with.
+fn color_hash(line: String) -> String {
+    match line.split_once(' ') {
+        Some((hash, rest)) => format!("{} {rest}", colored(ansi::YELLOW, hash)),
+        None => line,
+    }
+}
+
+/// Builds the git preview of `path`, or None if git has nothing to show so
+/// the regular preview is used instead.
+pub fn git_info(path: &Path, preview: GitPreview, tab_width: usize) -> Option<Info> {
+    let lines: Vec<String> = match preview {
+        GitPreview::File(view, _) => {
+            let (directory, name) = (path.parent()?, path.file_name()?);
+            let args: Vec<&OsStr> = match view {
+                GitView::Diff => ["diff", "HEAD", "--no-color", "--"]
+                    .map(OsStr::new)
+                    .to_vec(),
+                GitView::Log => [
+                    "log",
+                    "--no-color",
+                    "--date=short",
+                    "--format=%h %ad %an: %s",
+                    "--",
+                ]
+                .map(OsStr::new)
+                .to_vec(),
+                GitView::Blame => ["blame", "--date=short", "--"].map(OsStr::new).to_vec(),
+            };
+            let output = git(directory, args.into_iter().chain([name]))?;
+            let lines = output_lines(&output, tab_width).into_iter();
+            match view {
+                GitView::Diff => lines.map(color_diff_line).collect(),
+                GitView::Log | GitView::Blame => lines.map(color_hash).collect(),
+            }
+        }
+        GitPreview::Summary(_) => summary(path, tab_width)?,
+    };
+    (!lines.is_empty()).then(|| Info::with_lines(InfoType::Text, lines))
+}
+
+fn summary(root: &Path, tab_width: usize) -> Option<Vec<String>> {
+    let output = git(root, ["status", "--porcelain=v2", "--branch", "-z"])?;
+    let status = GitStatus::parse(root.to_path_buf(), &output);
+    let mut branch = colored(ansi::MAGENTA, &sanitize_name(&status.branch));
+    if status.ahead > 0 {
+        branch += &format!(" ↑{}", status.ahead);
+    }
+    if status.behind > 0 {
+        branch += &format!(" ↓{}", status.behind);
+    }
+    let counts: Vec<String> = [
+        (GitState::Conflicted, "conflicted"),
+        (GitState::Modified, "modified"),
+        (GitState::Staged, "staged"),
+        (GitState::Untracked, "untracked"),
+    ]
+    .into_iter()
+    .filter_map(|(state, name)| {
+        let count = status.files.values().filter(|s| **s == state).count();
+        (count > 0).then(|| format!("{count} {name}"))
+    })
+    .collect();
+
+    let mut lines = vec![branch];
+    lines.push(match counts.is_empty() {
+        true => String::from("clean"),
+        false => counts.join(", "),
+    });
+    lines.push(String::new());
+    let commits = SUMMARY_COMMITS.to_string();
+    if let Some(log) = git(root, ["log", "--no-color", "--oneline", "-n", &commits]) {
+        lines.extend(output_lines(&log, tab_width).into_iter().map(color_hash));
+        lines.push(String::new());
+    }
+    lines.extend_from_slice(Info::directory(&root.to_path_buf()).lines());
+    Some(lines)
+}
+
+impl App {
+    /// Runs `git status` in the background for the current directory's work
+    /// tree. If it is already running it runs again once it is done.
+    pub fn refresh_git_status(&mut self) {
+        let Some(root) = work_tree_root(&self.current_directory) else {
+            self.git_status = None;
+            self.git_loader = None;
+            return;
+        };
+        if self.git_loader.is_some() {
+            self.git_refresh_pending = true;
+            return;
+        }
+        let (sender, receiver) = channel();
+        std::thread::spawn(move || {
+            let _ = sender.send(read_status(root));
+        });
+        self.git_loader = Some(receiver);
+    }
+
+    pub fn receive_git_status(&mut self) {
+        let Some(receiver) = &self.git_loader else {
+            return;
+        };
+        match receiver.try_recv() {
+            Ok(status) => {
+                self.git_status = status;
+                // The selected file may have been changed or committed.
+                self.update_selected_item();
+            }
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => (),
+        }
+        self.git_loader = None;
+        if std::mem::take(&mut self.git_refresh_pending) {
+            self.refresh_git_status();
+        }
+    }
+
+    /// The marker for an entry of `directory`, or None if the directory
+    /// isn't in the work tree.
+    pub fn git_marker(&self, directory: &Path, name: Option<&OsStr>) -> Option<String> {
+        let status = self
+            .git_status
+            .as_ref()
+            .filter(|status| status.contains(directory))?;
+        let state = name.and_then(|name| status.state(&directory.join(name)));
+        Some(state.map_or_else(|| String::from("  "), GitState::marker))
+    }
+
+    pub fn cycle_git_view(&mut self) {
+        self.git_view = self.git_view.next();
+        self.msg(format!("Git preview: {}", self.git_view.name()));
+        self.update_selected_item();
+    }
+
+    /// The git preview to show for `path` instead of the regular one, if any.
+    pub fn git_preview(&self, path: &Path, ftype: FileType) -> Option<GitPreview> {
+        if ftype == FileType::Directory {
+            if !path.join(".git").exists() {
+                return None;
+            }
+            let git_dir = match &self.git_status {
+                Some(status) if status.root == path => status.git_dir.clone(),
+                _ => git_dir(path),
+            };
+            return Some(GitPreview::Summary(fingerprint(&git_dir)));
+        }
+        let status = self
+            .git_status
+            .as_ref()
+            .filter(|status| status.contains(path))?;
+        let state = status.state(path);
+        let shown = match self.git_view {
+            GitView::Diff => state.is_some_and(|state| state >= GitState::Staged),
+            GitView::Log | GitView::Blame => state.is_none_or(|state| state >= GitState::Staged),
+        };
+        shown.then(|| GitPreview::File(self.git_view, fingerprint(&status.git_dir)))
+    }
+}
@@ -0,0 +1,165 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{channel, TryRecvError};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::git::work_tree_root;
+use crate::{App, Prompt};
+
+#[derive(Debug, Clone)]
+enum GitAction {
+    Stage(Vec<PathBuf>),
+    Unstage(Vec<PathBuf>),
+    /// Stashes the working-tree changes of the files, keeping what is staged,
+    /// so `git stash pop` brings them back.
+    Discard(Vec<PathBuf>),
+    Commit(String),
+}
+
+impl GitAction {
+    fn args(&self) -> Vec<OsString> {
+        let (args, paths): (&[&str], &[PathBuf]) = match self {
+            GitAction::Stage(paths) => (&["add", "--"], paths),
+            GitAction::Unstage(paths) => (&["restore", "--staged", "--"], paths),
+            GitAction::Discard(paths) => (
+                &[
+                    "stash",
+                    "push",
+                    "--keep-index",
+                    "--message",
+                    "discarded in kranger",
+                    "--",
+                ],
+                paths,
+            ),
+            GitAction::Commit(message) => {
+                return ["commit", "--message", message]
+                    .map(OsString::from)
+                    .to_vec();
+            }
+        };
+        args.iter()
+            .map(OsString::from)
+            .chain(paths.iter().map(OsString::from))
+            .collect()
+    }
+
+    /// Runs git in `root` and describes how it went.
+    fn run(&self, root: &Path) -> String {
+        let output = match Command::new("git")
+            .arg("-C")
+            .arg(root)
+            .args(self.args())
+            .stdin(Stdio::null())
+            .output()
+        {
+            Ok(output) => output,
+            Err(err) => return format!("Unable to run git: {err}"),
+        };
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let reason = stderr.lines().chain(stdout.lines()).next().unwrap_or("");
+            return format!("git {} failed: {reason}", self.args()[0].to_string_lossy());
+        }
+        let files = |paths: &[PathBuf]| match paths.len() {
+            1 => String::from("1 file"),
+            count => format!("{count} files"),
+        };
+        match self {
+            GitAction::Stage(paths) => format!("Staged {}", files(paths)),
+            GitAction::Unstage(paths) => format!("Unstaged {}", files(paths)),
+            GitAction::Discard(_) if stdout.contains("No local changes") => {
+                String::from("No changes to discard")
+            }
+            GitAction::Discard(paths) => format!(
+                "Discarded changes to {}, `git stash pop` brings them back",
+                files(paths)
+            ),
+            GitAction::Commit(_) => stdout.lines().next().unwrap_or("Committed").to_owned(),
+        }
+    }
+}
+
+impl App {
+    /// The marked files, or the selected one if nothing is marked.
+    pub fn targets(&self) -> Result<Vec<PathBuf>> {
+        if self.marked.is_empty() {
+            self.selected_item
+                .clone()
+                .map(|path| vec![path])
+                .ok_or(anyhow!("No item selected!"))
+        } else {
+            Ok(self.marked.iter().cloned().collect())
+        }
+    }
+
+    fn start_git_job(&mut self, action: GitAction) -> Result<()> {
+        let Some(root) = work_tree_root(&self.current_directory) else {
+            bail!("Not in a git repository");
+        };
+        if self.git_job.is_some() {
+            bail!("A git command is still running");
+        }
+        let (sender, receiver) = channel();
+        std::thread::spawn(move || {
+            let _ = sender.send(action.run(&root));
+        });
+        self.git_job = Some(receiver);
+        Ok(())
+    }
+
+    pub fn receive_git_job(&mut self) {
+        let Some(receiver) = &self.git_job else {
+            return;
+        };
+        match receiver.try_recv() {
+            Ok(message) => self.msg(message),
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => (),
+        }
+        self.git_job = None;
+        self.refresh_git_status();
+    }
+
+    pub fn stage(&mut self) -> Result<()> {
+        let paths = self.targets()?;
+        self.start_git_job(GitAction::Stage(paths))
+    }
+
+    pub fn unstage(&mut self) -> Result<()> {
+        let paths = self.targets()?;
+        self.start_git_job(GitAction::Unstage(paths))
+    }
+
+    /// Asks before discarding the changes to the marked or selected files.
+    pub fn start_discard(&mut self) -> Result<()> {
+        self.confirm(Prompt::ConfirmDiscard)
+    }
+
+    /// Anything but y cancels.
+    pub fn answer_discard(&mut self, answer: char) -> Result<()> {
+        let paths = std::mem::take(&mut self.confirm_targets);
+        self.prompt = None;
+        match answer {
+            'y' | 'Y' => self.start_git_job(GitAction::Discard(paths)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn start_commit(&mut self) {
+        self.prompt = Some(Prompt::Commit);
+        self.commit_message.clear();
+    }
+
+    pub fn run_commit(&mut self) -> Result<()> {
+        self.prompt = None;
+        let message = std::mem::take(&mut self.commit_message);
+        if message.trim().is_empty() {
+            bail!("Empty commit message, nothing committed");
+        }
+        self.start_git_job(GitAction::Commit(message))
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use walkdir::{DirEntry, WalkDir};
+
+use crate::file::is_hidden;
+use crate::App;
+
+struct DirectoryRules {
+    /// `.gitignore`, and `.git/info/exclude` at the root of a repository.
+    gitignore: Option<Gitignore>,
+    /// `.ignore`, which also applies outside of repositories.
+    ignore: Option<Gitignore>,
+    is_repository_root: bool,
+}
+
+/// Answers whether a path is ignored, reading the ignore files of its
+/// ancestors the first time they are needed. `.gitignore` files only count
+/// inside a repository, `.ignore` files everywhere and over the `.gitignore`
+/// in the same directory.
+#[derive(Default)]
+pub struct IgnoreRules {
+    directories: HashMap<PathBuf, DirectoryRules>,
+}
+
+/// Whether `gitignore` ignores or explicitly includes the path, if either.
+fn decide(gitignore: &Option<Gitignore>, path: &Path, is_dir: bool) -> Option<bool> {
+    let matched = gitignore.as_ref()?.matched(path, is_dir);
+    (!matched.is_none()).then(|| matched.is_ignore())
+}
+
+/// Reads the files into one set of rules, later files taking precedence.
+fn read_rules(directory: &Path, files: &[PathBuf]) -> Option<Gitignore> {
+    let mut builder = GitignoreBuilder::new(directory);
+    let mut found = false;
+    for file in files.iter().filter(|file| file.is_file()) {
+        found |= builder.add(file).is_none();
+    }
+    found.then(|| builder.build().ok()).flatten()
+}
+
+impl IgnoreRules {
+    pub fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        if path.file_name().is_some_and(|name| name == ".git") {
+            return true;
+        }
+        // `.gitignore` files count up to the root of the repository.
+        let mut repository_depth = None;
+        for (depth, directory) in path.ancestors().skip(1).enumerate() {
+            if self.rules(directory).is_repository_root {
+                repository_depth = Some(depth);
+                break;
+            }
+        }
+        // The nearest directory with an opinion wins, like in git and
+        // ripgrep. Within a directory `.ignore` wins over `.gitignore`.
+        for (depth, directory) in path.ancestors().skip(1).enumerate() {
+            let rules = self.rules(directory);
+            let in_repository = repository_depth.is_some_and(|root| depth <= root);
+            let decision = decide(&rules.ignore, path, is_dir).or_else(|| {
+                in_repository
+                    .then(|| decide(&rules.gitignore, path, is_dir))
+                    .flatten()
+            });
+            if let Some(ignored) = decision {
+                return ignored;
+            }
+        }
+        false
+    }
+
+    fn rules(&mut self, directory: &Path) -> &DirectoryRules {
+        self.directories
+            .entry(directory.to_path_buf())
+            .or_insert_with(|| {
+                let is_repository_root = directory.join(".git").exists();
+                let mut gitignore_files = Vec::new();
+                if is_repository_root {
+                    gitignore_files.push(directory.join(".git/info/exclude"));
+                }
+                gitignore_files.push(directory.join(".gitignore"));
+                DirectoryRules {
+                    gitignore: read_rules(directory, &gitignore_files),
+                    ignore: read_rules(directory, &[directory.join(".ignore")]),
+                    is_repository_root,
+                }
+            })
+    }
+}
+
+/// Whether a change to `name` affects what is ignored.
+pub fn is_ignore_file(name: &OsStr) -> bool {
+    name == ".gitignore" || name == ".ignore"
+}
+
+impl App {
+    /// Whether `name` in `directory` is left out of listings because it is
+    /// ignored.
+    pub fn hides_ignored(&mut self, directory: &Path, name: &OsStr) -> bool {
+        if !self.hide_ignored {
+            return false;
+        }
+        let path = directory.join(name);
+        let is_dir = path.symlink_metadata().is_ok_and(|meta| meta.is_dir());
+        self.ignore_rules.is_ignored(&path, is_dir)
+    }
+}
+
+/// Everything below `root`, without hidden entries unless `show_hidden` and
+/// without what git ignores if `respect_gitignore`. Skipped directories are
+/// not descended into.
+pub fn walk_tree(
+    root: &Path,
+    show_hidden: bool,
+    respect_gitignore: bool,
+) -> impl Iterator<Item = DirEntry> {
+    walk_tree_to_depth(root, usize::MAX, show_hidden, respect_gitignore)
+}
+
+/// Like `walk_tree`, but at most `max_depth` levels deep.
+pub fn walk_tree_to_depth(
+    root: &Path,
+    max_depth: usize,
+    show_hidden: bool,
+    respect_gitignore: bool,
+) -> impl Iterator<Item = DirEntry> {
+    let mut rules = IgnoreRules::default();
+    WalkDir::new(root)
+        .min_depth(1)
+        .max_depth(max_depth)
+        .into_iter()
+        .filter_entry(move |entry| {
+            (show_hidden || !is_hidden(entry))
+                && !(respect_gitignore
+                    && rules.is_ignored(entry.path(), entry.file_type().is_dir()))
+        })
+        .filter_map(|entry| entry.ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory tree with ignore files, removed on drop.
+    struct Fixture(PathBuf);
+
+    impl Fixture {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let root = std::env::temp_dir().join(format!("kranger-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&root);
+            for (path, contents) in files {
+                let path = root.join(path);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, contents).unwrap();
+            }
+            Fixture(root)
+        }
+
+        fn is_ignored(&self, path: &str) -> bool {
+            IgnoreRules::default().is_ignored(&self.0.join(path), false)
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn deeper_negation_overrides_a_shallower_ignore() {
+        let fixture = Fixture::new(
+            "negation",
+            &[
+                (".git/HEAD", ""),
+                (".ignore", "*.log\n"),
+                ("sub/.gitignore", "!keep.log\n"),
+            ],
+        );
+        assert!(fixture.is_ignored("other.log"));
+        assert!(fixture.is_ignored("sub/other.log"));
+        assert!(!fixture.is_ignored("sub/keep.log"));
+        assert!(fixture.is_ignored("keep.log"));
+    }
+
+    #[test]
+    fn deeper_ignore_overrides_a_shallower_gitignore() {
+        let fixture = Fixture::new(
+            "deeper",
+            &[
+                (".git/HEAD", ""),
+                (".gitignore", "*.bak\n"),
+                ("sub/.ignore", "!x.bak\n"),
+            ],
+        );
+        assert!(fixture.is_ignored("x.bak"));
+        assert!(!fixture.is_ignored("sub/x.bak"));
+        assert!(fixture.is_ignored("sub/y.bak"));
+    }
+
+    #[test]
+    fn ignore_wins_within_a_directory() {
+        let fixture = Fixture::new(
+            "same_level",
+            &[
+                (".git/HEAD", ""),
+                (".gitignore", "*.tmp\n!a.o\n"),
+                (".ignore", "!b.tmp\n*.o\n"),
+            ],
+        );
+        assert!(fixture.is_ignored("a.tmp"));
+        assert!(!fixture.is_ignored("b.tmp"));
+        assert!(fixture.is_ignored("a.o"));
+    }
+
+    #[test]
+    fn gitignore_only_counts_inside_a_repository() {
+        let fixture = Fixture::new(
+            "outside",
+            &[
+                (".gitignore", "*.o\n"),
+                (".ignore", "*.tmp\n"),
+                ("repo/.git/HEAD", ""),
+                ("repo/.gitignore", "*.a\n"),
+            ],
+        );
+        assert!(!fixture.is_ignored("main.o"));
+        assert!(fixture.is_ignored("main.tmp"));
+        assert!(!fixture.is_ignored("repo/main.o"));
+        assert!(fixture.is_ignored("repo/main.a"));
+        assert!(fixture.is_ignored("repo/main.tmp"));
+        assert!(fixture.is_ignored("repo/.git"));
+    }
+}
@@ -0,0 +1,169 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Sender};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use regex::Regex;
+
+use crate::external::probably_valid_utf_bytes;
+use crate::file::{File, FileType};
+use crate::gitignore::walk_tree;
+use crate::info::{Info, InfoType};
+use crate::sanitize::sanitize_text;
+use crate::search::smart_case_regex;
+use crate::virtual_listing::VirtualEntry;
+use crate::{App, Prompt};
+
+const CONTEXT_LINES: usize = 2;
+const MAX_MATCHING_LINES: usize = 200;
+/// Bigger files are most likely data, not worth reading in full.
+const MAX_FILE_SIZE: u64 = 32 * 1024 * 1024;
+/// Width of the line number column in the previews, including the separator.
+pub const LINE_NUMBER_WIDTH: usize = 8;
+
+/// Files containing a match for `regex`, with the matching lines and some
+/// context as their preview. Files are read by a pool of worker threads
+/// while another thread walks the tree.
+pub fn grep(
+    root: PathBuf,
+    regex: Regex,
+    show_hidden: bool,
+    respect_gitignore: bool,
+    tab_width: usize,
+    sender: Sender<Vec<VirtualEntry>>,
+    cancelled: Arc<AtomicBool>,
+) {
+    let workers = std::thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(2)
+        .clamp(2, 8);
+    let (path_sender, path_receiver) = sync_channel::<PathBuf>(1024);
+    let path_receiver = Arc::new(Mutex::new(path_receiver));
+    let handles: Vec<_> = (0..workers)
+        .map(|_| {
+            let path_receiver = Arc::clone(&path_receiver);
+            let regex = regex.clone();
+            let sender = sender.clone();
+            let cancelled = Arc::clone(&cancelled);
+            let root = root.clone();
+            std::thread::spawn(move || loop {
+                let path = match path_receiver.lock().unwrap().recv() {
+                    Ok(path) => path,
+                    Err(_) => return,
+                };
+                if cancelled.load(Ordering::Relaxed) {
+                    return;
+                }
+                if let Some(lines) = grep_file(&root.join(&path), &regex, tab_width) {
+                    let entry = VirtualEntry {
+                        file: File::new(FileType::File, path.into_os_string()),
+                        preview: Some(Info::with_lines(InfoType::Text, lines)),
+                    };
+                    if sender.send(vec![entry]).is_err() {
+                        return;
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for entry in walk_tree(&root, show_hidden, respect_gitignore) {
+        if cancelled.load(Ordering::Relaxed) {
+            break;
+        }
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let path = entry.path().strip_prefix(&root).unwrap_or(entry.path());
+        if path_sender.send(path.to_path_buf()).is_err() {
+            break;
+        }
+    }
+    drop(path_sender);
+    for handle in handles {
+        let _ = handle.join();
+    }
+}
+
+/// The matching lines of a text file, grep -C style, or None if nothing
+/// matched.
+fn grep_file(path: &Path, regex: &Regex, tab_width: usize) -> Option<Vec<String>> {
+    if std::fs::metadata(path).ok()?.len() > MAX_FILE_SIZE {
+        return None;
+    }
+    let contents = std::fs::read(path).ok()?;
+    if !probably_valid_utf_bytes(&contents) {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&contents);
+    let lines: Vec<&str> = text.lines().collect();
+    let matching: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| regex.is_match(line))
+        .map(|(index, _)| index)
+        .take(MAX_MATCHING_LINES)
+        .collect();
+    if matching.is_empty() {
+        return None;
+    }
+
+    let mut output = Vec::new();
+    let mut printed_up_to = 0;
+    for (i, &index) in matching.iter().enumerate() {
+        let from = index.saturating_sub(CONTEXT_LINES).max(printed_up_to);
+        let to = (index + CONTEXT_LINES + 1)
+            .min(lines.len())
+            .min(matching.get(i + 1).copied().unwrap_or(usize::MAX));
+        if from > printed_up_to && printed_up_to > 0 {
+            output.push(String::from("--"));
+        }
+        for (line, text) in lines.iter().enumerate().take(to).skip(from) {
+            let separator = if line == index { ':' } else { '-' };
+            output.push(format!(
+                "{:>width$}{separator} {}",
+                line + 1,
+                sanitize_text(text, tab_width),
+                width = LINE_NUMBER_WIDTH - 2
+            ));
+        }
+        printed_up_to = to;
+    }
+    Some(output)
+}
+
+impl App {
+    pub fn start_grep(&mut self) {
+        self.prompt = Some(Prompt::Grep);
+        self.grep_pattern.clear();
+    }
+
+    pub fn run_grep(&mut self) -> Result<()> {
+        self.prompt = None;
+        if self.grep_pattern.is_empty() {
+            return Ok(());
+        }
+        let regex = smart_case_regex(&self.grep_pattern)?;
+        let root = self.current_directory.clone();
+        let (show_hidden, respect_gitignore) = (self.show_hidden, self.respect_gitignore);
+        let tab_width = self.tab_width;
+        let highlight = regex.clone();
+        self.start_virtual_listing(
+            format!("grep: {}", self.grep_pattern),
+            Some(highlight),
+            move |sender, cancelled| {
+                grep(
+                    root,
+                    regex,
+                    show_hidden,
+                    respect_gitignore,
+                    tab_width,
+                    sender,
+                    cancelled,
+                )
+            },
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Color, Theme, ThemeSet};
+use syntect::parsing::{SyntaxDefinition, SyntaxReference, SyntaxSet, SyntaxSetBuilder};
+
+use crate::ansi;
+
+const THEME: &str = "base16-ocean.dark";
+const TOML_SYNTAX: &str = include_str!("../assets/TOML.sublime-syntax");
+
+/// How many colors the terminal can show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorDepth {
+    TrueColor,
+    /// The 256 color palette.
+    Indexed,
+    /// The 8 basic colors.
+    Basic,
+}
+
+impl ColorDepth {
+    fn detect() -> Self {
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        let term = std::env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorDepth::TrueColor
+        } else if term.contains("256color") {
+            ColorDepth::Indexed
+        } else {
+            ColorDepth::Basic
+        }
+    }
+
+    fn foreground(self, Color { r, g, b, .. }: Color) -> String {
+        match self {
+            ColorDepth::TrueColor => format!("\x1B[38;2;{r};{g};{b}m"),
+            ColorDepth::Indexed => format!("\x1B[38;5;{}m", to_indexed(r, g, b)),
+            ColorDepth::Basic => format!("\x1B[{}m", 30 + to_basic(r, g, b)),
+        }
+    }
+}
+
+/// The closest entry of the 6x6x6 color cube or the gray ramp.
+fn to_indexed(r: u8, g: u8, b: u8) -> u8 {
+    if r == g && g == b {
+        return match r {
+            0..=7 => 16,
+            249.. => 231,
+            gray => 232 + ((gray - 8) / 10).min(23),
+        };
+    }
+    let level = |value: u8| match value {
+        0..=47 => 0,
+        48..=114 => 1,
+        value => (value - 35) / 40,
+    };
+    16 + 36 * level(r) + 6 * level(g) + level(b)
+}
+
+/// The basic color of the channels that dominate, so grays become white.
+/// Black would vanish on a dark background and becomes white too.
+fn to_basic(r: u8, g: u8, b: u8) -> u8 {
+    let max = r.max(g).max(b) as u16;
+    let bits = [r, g, b]
+        .iter()
+        .enumerate()
+        .filter(|(_, value)| **value as u16 * 3 > max * 2)
+        .fold(0, |bits, (channel, _)| bits | 1 << channel);
+    match bits {
+        0 => 7,
+        bits => bits,
+    }
+}
+
+struct Highlighter {
+    syntaxes: [SyntaxSet; 2],
+    theme: Theme,
+    depth: ColorDepth,
+}
+
+/// Loaded by the first preview that needs it, then shared by all workers.
+fn highlighter() -> &'static Highlighter {
+    static HIGHLIGHTER: OnceLock<Highlighter> = OnceLock::new();
+    HIGHLIGHTER.get_or_init(|| Highlighter {
+        syntaxes: [SyntaxSet::load_defaults_newlines(), extra_syntaxes()],
+        theme: ThemeSet::load_defaults()
+            .themes
+            .remove(THEME)
+            .unwrap_or_default(),
+        depth: ColorDepth::detect(),
+    })
+}
+
+/// Syntaxes missing from the bundled ones. They are kept in their own set,
+/// since adding them to the bundled set would relink all of it.
+fn extra_syntaxes() -> SyntaxSet {
+    let mut builder = SyntaxSetBuilder::new();
+    if let Ok(toml) = SyntaxDefinition::load_from_str(TOML_SYNTAX, true, None) {
+        builder.add(toml);
+    }
+    builder.build()
+}
+
+/// The syntax for the extension or name of `path`, or else its shebang,
+/// with the set it belongs to.
+fn find_syntax<'a>(
+    syntaxes: &'a [SyntaxSet],
+    path: &Path,
+    first_line: &str,
+) -> Option<(&'a SyntaxSet, &'a SyntaxReference)> {
+    let find = |by: &dyn Fn(&'a SyntaxSet) -> Option<&'a SyntaxReference>| {
+        syntaxes
+            .iter()
+            .find_map(|set| by(set).map(|syntax| (set, syntax)))
+    };
+    let by_name = |name: Option<&OsStr>| {
+        let name = name.and_then(OsStr::to_str)?;
+        find(&|set| set.find_syntax_by_extension(name))
+    };
+    by_name(path.extension())
+        .or_else(|| by_name(path.file_name()))
+        .or_else(|| find(&|set| set.find_syntax_by_first_line(first_line)))
+        .filter(|(_, syntax)| syntax.name != "Plain Text")
+}
+
+/// Colors the first `lines` of the file at `path`. They are returned
+/// unchanged if its syntax isn't known.
+pub fn highlight(path: &Path, lines: Vec<String>) -> Vec<String> {
+    let highlighter = highlighter();
+    let first_line = lines.first().map(String::as_str).unwrap_or_default();
+    let Some((syntaxes, syntax)) = find_syntax(&highlighter.syntaxes, path, first_line) else {
+        return lines;
+    };
+    let mut state = HighlightLines::new(syntax, &highlighter.theme);
+    let mut highlighted = Vec::with_capacity(lines.len());
+    for line in &lines {
+        let line = format!("{line}\n");
+        let Ok(ranges) = state.highlight_line(&line, syntaxes) else {
+            return lines;
+        };
+        let mut output = String::with_capacity(line.len() * 2);
+        let mut color = None;
+        for (style, text) in ranges {
+            let text = text.trim_end_matches('\n');
+            if text.is_empty() {
+                continue;
+            }
+            if color != Some(style.foreground) {
+                output += &highlighter.depth.foreground(style.foreground);
+                color = Some(style.foreground);
+            }
+            output += text;
+        }
+        if color.is_some() {
+            output += ansi::RESET;
+        }
+        highlighted.push(output);
+    }
+    highlighted
+}
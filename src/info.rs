@@ -1,5 +1,8 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
 use std::path::Path;
-use std::{fs::read_to_string, io::Read, os::unix::fs::MetadataExt, path::PathBuf};
+use std::sync::OnceLock;
+use std::{io::Read, os::unix::fs::MetadataExt, path::PathBuf};
 
 use anyhow::Result;
 use phf::phf_map;
@@ -7,27 +10,37 @@ use phf::phf_map;
 use crate::display::display_file;
 use crate::external::{probably_valid_utf, run_external_command};
 use crate::file::directory_contents;
+use crate::highlight::highlight;
+use crate::sanitize::sanitize_text;
+use crate::width::visible_width;
+
+// Far more than fits on screen, but formatting every entry of a huge
+// directory is wasted work.
+const MAX_DIRECTORY_LINES: usize = 1000;
 
 pub struct Info {
     pub info_type: InfoType,
 
     info_lines: Vec<String>,
+    /// Columns of the widest line, measured the first time it is asked for.
+    widest: OnceLock<usize>,
 }
 
 impl Info {
-    pub fn new(file: &PathBuf) -> Result<Self> {
+    pub fn new(file: &PathBuf, tab_width: usize, size: PreviewSize) -> Result<Self> {
         let info_type = InfoType::new(file)?;
 
         let info_lines = match info_type {
-            InfoType::Text | InfoType::ShellScript => read_to_string(file)
-                .unwrap_or_default()
-                .lines()
-                .take(50)
-                .map(|s| s.to_string())
-                .collect(),
+            InfoType::Text | InfoType::ShellScript => highlight(
+                file,
+                read_head(file, size)
+                    .iter()
+                    .map(|s| sanitize_text(s, tab_width))
+                    .collect(),
+            ),
             InfoType::Executable => {
-                let mut lines = match run_external_command("ldd", &[file.to_str().unwrap()]) {
-                    Ok(output) => output.unwrap(),
+                let mut lines = match run_external_command("ldd", &[file]) {
+                    Ok(output) => output.unwrap_or_default(),
                     Err(_err) => vec![String::from("Unable to run ldd")],
                 };
                 lines.insert(
@@ -40,52 +53,129 @@ impl Info {
                     ),
                 );
                 lines
+                    .iter()
+                    .map(|line| sanitize_text(line, tab_width))
+                    .collect()
             }
             InfoType::Audio | InfoType::Video => {
-                let mut lines = match run_external_command("metadata", &[file.to_str().unwrap()]) {
-                    Ok(output) => output.unwrap(),
+                let mut lines = match run_external_command("metadata", &[file]) {
+                    Ok(output) => output.unwrap_or_default(),
                     Err(_err) => vec![String::from("Unable to get metadata")],
                 };
                 lines.insert(0, format!("{info_type:?}"));
                 lines
+                    .iter()
+                    .map(|line| sanitize_text(line, tab_width))
+                    .collect()
             }
             _ => Vec::new(),
         };
 
-        Ok(Self {
+        Ok(Self::with_lines(info_type, info_lines))
+    }
+
+    /// Without any lines the preview shows the type.
+    pub fn with_lines(info_type: InfoType, mut info_lines: Vec<String>) -> Self {
+        if info_lines.is_empty() {
+            info_lines.push(format!("{info_type:?}"));
+        }
+        Self {
             info_type,
             info_lines,
-        })
+            widest: OnceLock::new(),
+        }
+    }
+
+    /// Shown instead of a preview that couldn't be built.
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::with_lines(InfoType::Unknown, vec![message.into()])
+    }
+
+    /// Placeholder shown while the real preview is built in the background.
+    pub fn loading(info_type: InfoType) -> Self {
+        Self::with_lines(info_type, vec![String::from("Loading…")])
     }
 
     pub fn link(_link: &Path) -> Self {
-        Self {
-            info_type: InfoType::Link,
-            info_lines: Vec::new(),
-        }
+        Self::with_lines(InfoType::Link, Vec::new())
     }
 
     pub fn directory(directory: &PathBuf) -> Self {
         let directory_contents = directory_contents(directory, false);
 
-        let mut info_lines = Vec::with_capacity(directory_contents.len());
-        for file in directory_contents {
-            info_lines.push(display_file(Some(&file), 50));
+        let mut info_lines = Vec::with_capacity(directory_contents.len().min(MAX_DIRECTORY_LINES));
+        for file in directory_contents.iter().take(MAX_DIRECTORY_LINES) {
+            info_lines.push(display_file(Some(file), 50));
         }
 
+        Self::with_lines(InfoType::Directory, info_lines)
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.info_lines
+    }
+
+    /// How far the preview can be scrolled sideways.
+    pub fn widest(&self) -> usize {
+        *self.widest.get_or_init(|| {
+            self.info_lines
+                .iter()
+                .map(|line| visible_width(line))
+                .max()
+                .unwrap_or(0)
+        })
+    }
+}
+
+/// How much of a file a preview shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PreviewSize {
+    pub lines: usize,
+    pub columns: usize,
+}
+
+impl Default for PreviewSize {
+    fn default() -> Self {
         Self {
-            info_type: InfoType::Directory,
-            info_lines,
+            lines: 50,
+            columns: 80,
         }
     }
+}
 
-    pub fn lines(&self) -> Vec<String> {
-        if self.info_lines.is_empty() {
-            vec![format!("{:?}", self.info_type)]
-        } else {
-            self.info_lines.clone()
+/// Reads the lines of `file` that fit in `size` without reading any further,
+/// so previewing a huge log stays cheap. Lines are cut after enough bytes to
+/// fill the columns, which caps single-line files like minified JSON.
+fn read_head(file: &Path, size: PreviewSize) -> Vec<String> {
+    let Ok(file) = File::open(file) else {
+        return Vec::new();
+    };
+    // Enough for the widest characters, and for tabs to shrink a bit.
+    let line_bytes = size.columns.max(1) * 4;
+    let mut reader = BufReader::new(file.take((size.lines * line_bytes) as u64));
+    let mut lines = Vec::with_capacity(size.lines);
+    let mut line = Vec::with_capacity(line_bytes);
+    while lines.len() < size.lines {
+        line.clear();
+        match reader
+            .by_ref()
+            .take(line_bytes as u64)
+            .read_until(b'\n', &mut line)
+        {
+            Ok(0) | Err(_) => break,
+            Ok(_) => (),
         }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+        } else if line.len() == line_bytes && reader.skip_until(b'\n').is_err() {
+            break;
+        }
+        lines.push(String::from_utf8_lossy(&line).into_owned());
     }
+    lines
 }
 
 static KNOWN_NAMES: phf::Map<&'static str, InfoType> = phf_map! {
@@ -93,7 +183,7 @@ static KNOWN_NAMES: phf::Map<&'static str, InfoType> = phf_map! {
     ".gitignore" => InfoType::Text,
 };
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum InfoType {
     Executable,
     Text,
@@ -109,25 +199,41 @@ pub enum InfoType {
 
 impl InfoType {
     pub fn new(file: &PathBuf) -> Result<Self> {
-        let result = if let Some(extension) = file.extension() {
-            InfoType::from_extension(extension.to_str())
-        } else if let Some(info_type) = KNOWN_NAMES.get(
-            file.file_name()
-                .unwrap_or_default()
-                .to_str()
-                .unwrap_or_default(),
-        ) {
-            *info_type
+        Ok(InfoType::from_name(file).unwrap_or_else(|| InfoType::from_contents(file)))
+    }
+
+    /// The type told by the extension or a well known name, None if only the
+    /// contents can tell.
+    pub fn from_name(file: &Path) -> Option<Self> {
+        if let Some(extension) = file.extension() {
+            Some(InfoType::from_extension(extension.to_str()))
         } else {
-            InfoType::from_contents(file)
-        };
-        Ok(result)
+            KNOWN_NAMES
+                .get(
+                    file.file_name()
+                        .unwrap_or_default()
+                        .to_str()
+                        .unwrap_or_default(),
+                )
+                .copied()
+        }
+    }
+
+    /// Whether the preview of `path` could be text, without reading it.
+    pub fn may_be_text(path: &Path) -> bool {
+        match path.extension() {
+            Some(extension) => matches!(
+                InfoType::from_extension(extension.to_str()),
+                InfoType::Text | InfoType::ShellScript
+            ),
+            None => true,
+        }
     }
 
     pub fn from_extension(extension: Option<&str>) -> Self {
         match extension {
             Some(extension) => match extension {
-                "rs" | "md" | "txt" | "toml" | "lock" | "ini" => Self::Text,
+                "rs" | "md" | "txt" | "toml" | "lock" | "ini" | "log" | "json" => Self::Text,
                 "exe" => Self::Executable,
                 "png" | "jpg" | "jpeg" => Self::Image,
                 "opus" | "flac" | "mp3" | "wav" | "ogg" => Self::Audio,
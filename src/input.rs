@@ -1,4 +1,4 @@
-use crate::{App, ApplicationEvent};
+use crate::{App, ApplicationEvent, Prompt};
 
 use crossterm::event::{poll, read, Event, KeyCode, KeyModifiers};
 
@@ -7,7 +7,11 @@ impl App {
         if poll(std::time::Duration::from_millis(50))? {
             if let Event::Key(key_event) = read()? {
                 let (key, modifiers) = (key_event.code, key_event.modifiers);
-                if let Some(event) = self.resolve_keybinding(key, modifiers) {
+                let event = match self.prompt {
+                    Some(prompt) => prompt_keybinding(prompt, key, modifiers),
+                    None => self.resolve_keybinding(key, modifiers),
+                };
+                if let Some(event) = event {
                     self.new_events.push(event);
                 }
             }
@@ -21,6 +25,7 @@ impl App {
             // close
             (KeyCode::Esc, KeyModifiers::NONE),
             (KeyCode::Char('c'), KeyModifiers::CONTROL),
+            (KeyCode::Char('z'), KeyModifiers::CONTROL),
             // navigation
             (KeyCode::Char('a'), KeyModifiers::NONE),
             (KeyCode::Char('d'), KeyModifiers::NONE),
@@ -35,12 +40,48 @@ impl App {
             (KeyCode::Char('f'), KeyModifiers::NONE),
             (KeyCode::Char('p'), KeyModifiers::NONE),
             (KeyCode::Char('q'), KeyModifiers::NONE),
+            (KeyCode::Char(' '), KeyModifiers::NONE),
+            (KeyCode::Char('l'), KeyModifiers::NONE),
+            (KeyCode::Char('o'), KeyModifiers::NONE),
+            (KeyCode::Char('O'), KeyModifiers::SHIFT),
+            (KeyCode::Char('F'), KeyModifiers::SHIFT),
+            (KeyCode::Char('/'), KeyModifiers::NONE),
+            (KeyCode::Char('f'), KeyModifiers::CONTROL),
+            (KeyCode::Char('n'), KeyModifiers::NONE),
+            (KeyCode::Char('N'), KeyModifiers::SHIFT),
+            (KeyCode::Char('t'), KeyModifiers::CONTROL),
+            (KeyCode::Char('G'), KeyModifiers::SHIFT),
+            (KeyCode::Char('e'), KeyModifiers::CONTROL),
+            (KeyCode::Char('x'), KeyModifiers::NONE),
+            (KeyCode::Char('v'), KeyModifiers::NONE),
+            (KeyCode::Char('V'), KeyModifiers::SHIFT),
+            (KeyCode::Char('['), KeyModifiers::NONE),
+            (KeyCode::Char(']'), KeyModifiers::NONE),
+            (KeyCode::Char('t'), KeyModifiers::NONE),
+            (KeyCode::Char('H'), KeyModifiers::SHIFT),
+            (KeyCode::Char('g'), KeyModifiers::NONE),
+            (KeyCode::Char('S'), KeyModifiers::SHIFT),
+            (KeyCode::Char('U'), KeyModifiers::SHIFT),
+            (KeyCode::Char('X'), KeyModifiers::SHIFT),
+            (KeyCode::Char('C'), KeyModifiers::SHIFT),
+            (KeyCode::Up, KeyModifiers::SHIFT),
+            (KeyCode::Down, KeyModifiers::SHIFT),
+            (KeyCode::PageUp, KeyModifiers::NONE),
+            (KeyCode::PageDown, KeyModifiers::NONE),
+            (KeyCode::Left, KeyModifiers::SHIFT),
+            (KeyCode::Right, KeyModifiers::SHIFT),
+            (KeyCode::Char('W'), KeyModifiers::SHIFT),
+            (KeyCode::Char('L'), KeyModifiers::SHIFT),
+            (KeyCode::Backspace, KeyModifiers::NONE),
+            (KeyCode::Char('}'), KeyModifiers::NONE),
+            (KeyCode::Char('{'), KeyModifiers::NONE),
         ];
 
         let events_for_default_keybindings = vec![
             //close
             ApplicationEvent::Close,
             ApplicationEvent::Close,
+            ApplicationEvent::Suspend,
             //navigation
             ApplicationEvent::NavigateUp,
             ApplicationEvent::NavigateDown,
@@ -55,6 +96,41 @@ impl App {
             ApplicationEvent::OpenImageFolder,
             ApplicationEvent::PlayMedia,
             ApplicationEvent::DebugEvent,
+            ApplicationEvent::ToggleMark,
+            ApplicationEvent::ToggleLongListing,
+            ApplicationEvent::CycleSortKey,
+            ApplicationEvent::ToggleSortReverse,
+            ApplicationEvent::ToggleDirectoriesFirst,
+            ApplicationEvent::StartFilter,
+            ApplicationEvent::StartSearch,
+            ApplicationEvent::SearchNext,
+            ApplicationEvent::SearchPrevious,
+            ApplicationEvent::StartFinder,
+            ApplicationEvent::StartGrep,
+            ApplicationEvent::StartFind,
+            ApplicationEvent::Trash,
+            ApplicationEvent::ToggleFlatten,
+            ApplicationEvent::ToggleFlattenFilesOnly,
+            ApplicationEvent::ChangeFlattenDepth(-1),
+            ApplicationEvent::ChangeFlattenDepth(1),
+            ApplicationEvent::ToggleTreeView,
+            ApplicationEvent::ToggleHideIgnored,
+            ApplicationEvent::CycleGitView,
+            ApplicationEvent::Stage,
+            ApplicationEvent::Unstage,
+            ApplicationEvent::Discard,
+            ApplicationEvent::StartCommit,
+            ApplicationEvent::ScrollPreview(-1),
+            ApplicationEvent::ScrollPreview(1),
+            ApplicationEvent::ScrollPreviewPages(-1),
+            ApplicationEvent::ScrollPreviewPages(1),
+            ApplicationEvent::ScrollPreviewSideways(-1),
+            ApplicationEvent::ScrollPreviewSideways(1),
+            ApplicationEvent::ToggleSoftWrap,
+            ApplicationEvent::CycleLongListingFields,
+            ApplicationEvent::ClearFilter,
+            ApplicationEvent::SearchPreview(true),
+            ApplicationEvent::SearchPreview(false),
         ];
         for ((key, modifiers), event) in default_keybindings
             .into_iter()
@@ -81,3 +157,32 @@ impl App {
         self.keybindings.get(&(key, modifiers)).copied()
     }
 }
+
+/// Keys while typing into a prompt, everything printable goes into the text.
+fn prompt_keybinding(
+    prompt: Prompt,
+    key: KeyCode,
+    modifiers: KeyModifiers,
+) -> Option<ApplicationEvent> {
+    match (key, modifiers) {
+        (KeyCode::Esc, _) => Some(ApplicationEvent::CancelPrompt),
+        (KeyCode::Enter, _) => Some(ApplicationEvent::AcceptPrompt),
+        (KeyCode::Backspace, _) => Some(ApplicationEvent::PromptPop),
+        (KeyCode::Tab, _) if prompt == Prompt::Filter => Some(ApplicationEvent::CycleFilterMode),
+        (KeyCode::Up, _) => Some(ApplicationEvent::SelectPrevious),
+        (KeyCode::Down, _) => Some(ApplicationEvent::SelectNext),
+        (KeyCode::Char('p'), KeyModifiers::CONTROL) => Some(ApplicationEvent::SelectPrevious),
+        (KeyCode::Char('n'), KeyModifiers::CONTROL) => Some(ApplicationEvent::SelectNext),
+        (KeyCode::Char('g'), KeyModifiers::CONTROL) if prompt == Prompt::Finder => {
+            Some(ApplicationEvent::ToggleGitignore)
+        }
+        (KeyCode::Char('c'), KeyModifiers::CONTROL) => Some(ApplicationEvent::Close),
+        (KeyCode::Char('s'), KeyModifiers::CONTROL) if prompt == Prompt::Filter => {
+            Some(ApplicationEvent::ToggleStickyFilters)
+        }
+        (KeyCode::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+            Some(ApplicationEvent::PromptPush(c))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,307 @@
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use lru::LruCache;
+use walkdir::WalkDir;
+
+use crate::file::{is_hidden, File};
+use crate::gitignore::IgnoreRules;
+use crate::sort::SortOrder;
+use crate::App;
+
+// The first batch is about a screenful so something can be drawn right away,
+// later batches double in size to keep the number of merges logarithmic.
+const FIRST_BATCH: usize = 64;
+const MAX_BATCH: usize = 16384;
+const FIRST_BATCH_WAIT: Duration = Duration::from_millis(50);
+const CACHED_LISTINGS: usize = 16;
+
+/// Reads a directory on a background thread and hands out sorted batches.
+/// Dropping the loader stops the thread.
+pub struct ListingLoader {
+    receiver: Receiver<Vec<File>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ListingLoader {
+    pub fn spawn(
+        directory: &Path,
+        show_hidden: bool,
+        hide_ignored: bool,
+        order: SortOrder,
+    ) -> Self {
+        let (sender, receiver) = channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let directory = directory.to_path_buf();
+        let thread_cancelled = Arc::clone(&cancelled);
+        std::thread::spawn(move || {
+            let compare = |f1: &File, f2: &File| order.compare(f1, f2, &directory);
+            let mut rules = IgnoreRules::default();
+            let mut batch_size = FIRST_BATCH;
+            let mut batch = Vec::with_capacity(batch_size);
+            let entries = WalkDir::new(&directory)
+                .max_depth(1)
+                .min_depth(1)
+                .into_iter()
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| show_hidden || !is_hidden(entry))
+                .filter(|entry| {
+                    !(hide_ignored && rules.is_ignored(entry.path(), entry.file_type().is_dir()))
+                });
+            for entry in entries {
+                if thread_cancelled.load(Ordering::Relaxed) {
+                    return;
+                }
+                batch.push(File::from_entry(&entry));
+                if batch.len() >= batch_size {
+                    batch.sort_by(compare);
+                    if sender.send(std::mem::take(&mut batch)).is_err() {
+                        return;
+                    }
+                    batch_size = (batch_size * 2).min(MAX_BATCH);
+                    batch.reserve(batch_size);
+                }
+            }
+            batch.sort_by(compare);
+            let _ = sender.send(batch);
+        });
+
+        Self {
+            receiver,
+            cancelled,
+        }
+    }
+
+    /// Waits briefly for the first batch, so small directories are drawn in
+    /// full on the first frame.
+    pub fn first_batch(&self) -> Option<Vec<File>> {
+        self.receiver.recv_timeout(FIRST_BATCH_WAIT).ok()
+    }
+
+    /// Everything received so far, and whether the directory is fully read.
+    pub fn receive(&self) -> (Vec<Vec<File>>, bool) {
+        let mut batches = Vec::new();
+        loop {
+            match self.receiver.try_recv() {
+                Ok(batch) => batches.push(batch),
+                Err(TryRecvError::Empty) => return (batches, false),
+                Err(TryRecvError::Disconnected) => return (batches, true),
+            }
+        }
+    }
+}
+
+impl Drop for ListingLoader {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Whether hidden entries are shown, and whether ignored ones are hidden.
+type Visibility = (bool, bool);
+
+/// Complete listings of recently visited directories. An entry is only
+/// valid while the directory's mtime and the sort order are unchanged.
+pub struct ListingCache {
+    listings: LruCache<(PathBuf, Visibility), (SystemTime, SortOrder, Vec<File>)>,
+}
+
+impl ListingCache {
+    pub fn new() -> Self {
+        Self {
+            listings: LruCache::new(NonZeroUsize::new(CACHED_LISTINGS).unwrap()),
+        }
+    }
+
+    pub fn store(
+        &mut self,
+        directory: &Path,
+        visibility: Visibility,
+        order: SortOrder,
+        files: Vec<File>,
+    ) {
+        if let Some(mtime) = directory_mtime(directory) {
+            self.listings
+                .put((directory.to_path_buf(), visibility), (mtime, order, files));
+        }
+    }
+
+    pub fn take(
+        &mut self,
+        directory: &Path,
+        visibility: Visibility,
+        order: SortOrder,
+    ) -> Option<Vec<File>> {
+        let (mtime, cached_order, mut files) =
+            self.listings.pop(&(directory.to_path_buf(), visibility))?;
+        // Editing a file in place leaves the mtime of its directory alone,
+        // so whatever was read about the entries may be stale, and with it
+        // an order by size or time.
+        if Some(mtime) != directory_mtime(directory) || cached_order != order || order.reads_disk()
+        {
+            return None;
+        }
+        files.iter_mut().for_each(File::clear_cached);
+        Some(files)
+    }
+}
+
+fn directory_mtime(directory: &Path) -> Option<SystemTime> {
+    std::fs::metadata(directory)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// Merges a sorted batch into a sorted listing. Entries that are already
+/// present, for example because inotify reported them first, are skipped.
+pub fn merge_sorted(files: &mut Vec<File>, batch: Vec<File>, order: &SortOrder, directory: &Path) {
+    if batch.is_empty() {
+        return;
+    }
+    let existing = std::mem::take(files);
+    let mut merged = Vec::with_capacity(existing.len() + batch.len());
+    let mut existing = existing.into_iter().peekable();
+    let mut batch = batch.into_iter().peekable();
+    loop {
+        let next = match (existing.peek(), batch.peek()) {
+            (Some(a), Some(b)) => match order.compare(a, b, directory) {
+                std::cmp::Ordering::Greater => batch.next(),
+                std::cmp::Ordering::Equal => {
+                    batch.next();
+                    existing.next()
+                }
+                std::cmp::Ordering::Less => existing.next(),
+            },
+            (Some(_), None) => existing.next(),
+            (None, Some(_)) => batch.next(),
+            (None, None) => break,
+        };
+        merged.extend(next);
+    }
+    *files = merged;
+}
+
+impl App {
+    /// Fills both listings from the cache when possible, otherwise starts
+    /// loading them in the background.
+    pub fn load_listings(&mut self, use_cache: bool) {
+        let parent_directory = self.parent_directory().unwrap_or("\\".into());
+        // A virtual listing stands in for the current directory's.
+        if self.virtual_listing.is_none() {
+            let (current_contents, current_loader) =
+                self.start_listing(&self.current_directory.clone(), use_cache);
+            self.current_directory_contents = current_contents;
+            self.filtered_out.clear();
+            self.apply_filter();
+            self.current_loader = current_loader;
+        }
+        let (parent_contents, parent_loader) = self.start_listing(&parent_directory, use_cache);
+        self.parent_directory_contents = parent_contents;
+        self.parent_loader = parent_loader;
+    }
+
+    fn start_listing(
+        &mut self,
+        directory: &Path,
+        use_cache: bool,
+    ) -> (Vec<File>, Option<ListingLoader>) {
+        let order = self.sort_order(directory);
+        if use_cache {
+            if let Some(files) = self.listing_cache.take(directory, self.visibility(), order) {
+                return (files, None);
+            }
+        }
+        let loader = ListingLoader::spawn(directory, self.show_hidden, self.hide_ignored, order);
+        let files = loader.first_batch().unwrap_or_default();
+        (files, Some(loader))
+    }
+
+    /// Keeps the complete listings around so coming back to them is instant.
+    pub fn stash_listings(&mut self) {
+        // Until the new directory is loaded the listings belong to the old one.
+        if self.directory_changed {
+            return;
+        }
+        self.stash_current_listing();
+        if self.parent_loader.is_none() {
+            if let Some(parent) = self.parent_directory() {
+                let files = std::mem::take(&mut self.parent_directory_contents);
+                let order = self.sort_order(&parent);
+                self.listing_cache
+                    .store(&parent, self.visibility(), order, files);
+            }
+        }
+    }
+
+    pub fn stash_current_listing(&mut self) {
+        if self.directory_changed || self.virtual_listing.is_some() {
+            return;
+        }
+        self.unfilter();
+        if self.current_loader.is_none() {
+            let files = std::mem::take(&mut self.current_directory_contents);
+            let order = self.sort_order(&self.current_directory);
+            self.listing_cache
+                .store(&self.current_directory, self.visibility(), order, files);
+        }
+    }
+
+    pub fn receive_listings(&mut self) {
+        if let Some(loader) = &self.current_loader {
+            let (batches, finished) = loader.receive();
+            if finished {
+                self.current_loader = None;
+            }
+            if !batches.is_empty() {
+                let selected = self.selected_name();
+                for batch in batches {
+                    self.merge_into_current(batch);
+                }
+                // Keep the cursor on the same file while entries are merged in,
+                // unless the one we were waiting for arrived.
+                if self.select_pending() {
+                    self.update_selected_item();
+                } else {
+                    match selected {
+                        Some(selected) => {
+                            self.select_by_name(&selected);
+                        }
+                        None => self.update_selected_item(),
+                    }
+                }
+            }
+            if finished {
+                self.pending_selection = None;
+            }
+        }
+
+        if let Some(loader) = &self.parent_loader {
+            let (batches, finished) = loader.receive();
+            if finished {
+                self.parent_loader = None;
+            }
+            if let Some(parent) = self.parent_directory() {
+                let order = self.sort_order(&parent);
+                for batch in batches {
+                    merge_sorted(&mut self.parent_directory_contents, batch, &order, &parent);
+                }
+            }
+        }
+    }
+
+    fn visibility(&self) -> Visibility {
+        (self.show_hidden, self.hide_ignored)
+    }
+
+    pub fn is_loading_listing(&self) -> bool {
+        self.current_loader.is_some()
+            || self
+                .virtual_listing
+                .as_ref()
+                .is_some_and(|listing| listing.is_loading())
+    }
+}
@@ -0,0 +1,143 @@
+use std::path::Path;
+
+use crate::ansi;
+use crate::file::{File, FileType};
+use crate::metadata::{format_time, human_size, permissions_string};
+use crate::width::display_width;
+use crate::App;
+use LongListingField::{Modified, Permissions, Size};
+
+// Fields are dropped, last first, until the name has at least this much room.
+const MIN_NAME_WIDTH: usize = 16;
+
+/// The field lists cycled through, after the configured one.
+const PRESETS: &[&[LongListingField]] = &[
+    &[Size, Modified, Permissions],
+    &[Size, Modified],
+    &[Permissions, Size, Modified],
+    &[Modified],
+    &[Size],
+];
+
+/// A column shown next to each name in the long listing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LongListingField {
+    Permissions,
+    Size,
+    Modified,
+}
+
+impl LongListingField {
+    fn parse(name: &str) -> Option<Self> {
+        match name.trim() {
+            "permissions" | "perms" => Some(Permissions),
+            "size" => Some(Size),
+            "modified" | "mtime" => Some(Modified),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LongListingField::Permissions => "permissions",
+            LongListingField::Size => "size",
+            LongListingField::Modified => "modified",
+        }
+    }
+
+    fn width(&self) -> usize {
+        match self {
+            LongListingField::Permissions => 10,
+            LongListingField::Size => 6,
+            LongListingField::Modified => 16,
+        }
+    }
+
+    fn format(&self, file: &File, directory: &Path) -> String {
+        let metadata = file.metadata(directory);
+        let text = match (self, metadata) {
+            (_, None) => String::from("?"),
+            (LongListingField::Permissions, Some(metadata)) => permissions_string(metadata),
+            // Directories show how many entries they have instead of bytes.
+            (LongListingField::Size, Some(_)) if file.ftype == FileType::Directory => file
+                .entry_count(directory)
+                .map(|count| count.to_string())
+                .unwrap_or(String::from("?")),
+            (LongListingField::Size, Some(metadata)) => human_size(metadata.len()),
+            (LongListingField::Modified, Some(metadata)) => metadata
+                .modified()
+                .map(format_time)
+                .unwrap_or(String::from("?")),
+        };
+        let padding = " ".repeat(self.width().saturating_sub(display_width(&text)));
+        match self {
+            LongListingField::Size => format!("{padding}{text}"),
+            _ => format!("{text}{padding}"),
+        }
+    }
+}
+
+/// The fields that fit next to a name in a column of `width`, in order.
+pub fn fitting_fields(fields: &[LongListingField], width: usize) -> &[LongListingField] {
+    let mut used = MIN_NAME_WIDTH;
+    let mut count = 0;
+    for field in fields {
+        used += field.width() + 1;
+        if used > width {
+            break;
+        }
+        count += 1;
+    }
+    &fields[..count]
+}
+
+/// The text that goes after the name, starting with a separating space.
+pub fn format_fields(fields: &[LongListingField], file: &File, directory: &Path) -> String {
+    let mut output = String::from(ansi::GRAY);
+    for field in fields {
+        output.push(' ');
+        output.push_str(&field.format(file, directory));
+    }
+    output.push_str(ansi::RESET);
+    output
+}
+
+pub fn fields_width(fields: &[LongListingField]) -> usize {
+    fields.iter().map(|field| field.width() + 1).sum()
+}
+
+/// The field lists to cycle through. KRANGER_LONG_LISTING comes first, which
+/// names the fields in order, like "modified,size".
+pub fn field_presets() -> Vec<Vec<LongListingField>> {
+    let configured = std::env::var("KRANGER_LONG_LISTING")
+        .ok()
+        .and_then(|names| {
+            names
+                .split(',')
+                .map(LongListingField::parse)
+                .collect::<Option<Vec<_>>>()
+        })
+        .filter(|fields| !fields.is_empty());
+    let mut presets: Vec<Vec<LongListingField>> = configured.into_iter().collect();
+    for preset in PRESETS {
+        if !presets.iter().any(|fields| fields == preset) {
+            presets.push(preset.to_vec());
+        }
+    }
+    presets
+}
+
+impl App {
+    /// Switches to the next list of long listing fields.
+    pub fn cycle_long_listing_fields(&mut self) {
+        let current = self
+            .long_listing_presets
+            .iter()
+            .position(|fields| *fields == self.long_listing_fields);
+        let next = current.map_or(0, |index| (index + 1) % self.long_listing_presets.len());
+        self.long_listing_fields = self.long_listing_presets[next].clone();
+        self.long_listing = true;
+        let names: Vec<&str> = self.long_listing_fields.iter().map(|f| f.name()).collect();
+        self.msg(format!("Long listing: {}", names.join(", ")));
+    }
+}
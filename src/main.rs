@@ -1,24 +1,65 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
 use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
 use std::{collections::HashMap, process::Child};
 
 use anyhow::Result;
 use crossterm::event::{KeyCode, KeyModifiers};
 use file::File;
+use filter::Filter;
+use finder::Finder;
+use git::{GitStatus, GitView};
+use gitignore::IgnoreRules;
 use info::Info;
+use listing::{ListingCache, ListingLoader};
+use long_listing::LongListingField;
+use preview::Previewer;
+use screen::Screen;
+use search::{PreviewMatches, Search};
+use signal_hook::iterator::Signals;
+use sort::{SortKeyLoader, SortOrder};
+use virtual_listing::VirtualListing;
+use watch::Watcher;
 
 mod ansi;
+mod case;
 mod display;
 mod external;
 mod file;
+mod filter;
+mod find;
+mod finder;
+mod flatten;
+mod fuzzy;
+mod git;
+mod git_job;
+mod gitignore;
+mod grep;
+mod highlight;
 mod info;
 mod input;
+mod listing;
+mod long_listing;
+mod metadata;
+mod preview;
+mod preview_pane;
+mod sanitize;
+mod screen;
+mod search;
+mod signals;
+mod sort;
+mod tree;
 mod update;
+mod virtual_listing;
+mod watch;
+mod width;
+
+const DEFAULT_TAB_WIDTH: usize = 4;
 
 /*
     TODO:
-    use trash and be able to remove files
-    japanese things take more space than I expect,
-    some long russian string takes less for some reason,
     L to play media with --loop
     do something with pdf's
     maybe save index positions to not start from the top every time
@@ -36,19 +77,83 @@ struct App {
     current_directory: PathBuf,
     current_selection: usize,
     selected_item: Option<PathBuf>,
+    marked: HashSet<PathBuf>,
+    free_space: Option<u64>,
 
     current_directory_contents: Vec<File>,
+    /// Entries of the current directory hidden by the filter.
+    filtered_out: Vec<File>,
     parent_directory_contents: Vec<File>,
-    selection_info: Option<Info>,
+    current_loader: Option<ListingLoader>,
+    parent_loader: Option<ListingLoader>,
+    listing_cache: ListingCache,
+    virtual_listing: Option<VirtualListing>,
+    selection_info: Option<Arc<Info>>,
+    previewer: Previewer,
+    /// The first preview line shown, and how many columns it is scrolled
+    /// sideways.
+    preview_scroll: usize,
+    preview_column: usize,
+    soft_wrap: bool,
+    /// Lines a preview search wants read, to look past what the preview
+    /// shows.
+    preview_search_lines: usize,
+    /// A preview search waiting for more of the file, with the preview it
+    /// had searched.
+    pending_preview_search: Option<(bool, Arc<Info>)>,
+    /// Shown in the status line while searching.
+    preview_matches: Option<PreviewMatches>,
+    tab_width: usize,
 
     should_run: bool,
     directory_changed: bool,
     show_hidden: bool,
+    /// Leaves out what .gitignore and .ignore files match.
+    hide_ignored: bool,
+    ignore_rules: IgnoreRules,
+    git_status: Option<GitStatus>,
+    git_loader: Option<Receiver<Option<GitStatus>>>,
+    git_refresh_pending: bool,
+    git_view: GitView,
+    git_job: Option<Receiver<String>>,
+    trash_job: Option<Receiver<String>>,
+    commit_message: String,
+    /// What an action waiting for confirmation applies to.
+    confirm_targets: Vec<PathBuf>,
+    /// How many of them aren't in the listing, like marks made elsewhere.
+    confirm_not_shown: usize,
+    long_listing: bool,
+    default_sort_order: SortOrder,
+    sort_orders: HashMap<PathBuf, SortOrder>,
+    /// Reads the keys of a new sort order before the listing is sorted.
+    sort_key_loader: Option<SortKeyLoader>,
+    filter: Option<Filter>,
+    prompt: Option<Prompt>,
+    search: Option<Search>,
+    finder: Option<Finder>,
+    grep_pattern: String,
+    find_query: String,
+    flatten: bool,
+    flatten_depth: usize,
+    flatten_files_only: bool,
+    tree_view: bool,
+    /// Directories expanded in the tree view.
+    expanded: HashSet<PathBuf>,
+    respect_gitignore: bool,
+    /// Entry to put the cursor on once it shows up in the current listing.
+    pending_selection: Option<OsString>,
+    sticky_filters: bool,
+    saved_filters: HashMap<PathBuf, Filter>,
+    long_listing_fields: Vec<LongListingField>,
+    long_listing_presets: Vec<Vec<LongListingField>>,
 
     keybindings: HashMap<(KeyCode, KeyModifiers), ApplicationEvent>,
+    screen: Screen,
 
     new_events: Vec<ApplicationEvent>,
     children: Vec<Child>,
+    signals: Option<Signals>,
+    watcher: Option<Watcher>,
 
     debug_messages: Vec<String>,
 }
@@ -57,6 +162,8 @@ impl App {
     pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
         let _starting_directory = std::env::current_dir().unwrap();
         let current_directory = _starting_directory.clone();
+        let tab_width = tab_width();
+        let long_listing_presets = long_listing::field_presets();
 
         Ok(Self {
             width: 80,
@@ -65,25 +172,76 @@ impl App {
             current_directory,
             current_selection: 0,
             selected_item: None,
+            marked: HashSet::new(),
+            free_space: None,
 
             current_directory_contents: Vec::new(),
+            filtered_out: Vec::new(),
             parent_directory_contents: Vec::new(),
+            current_loader: None,
+            parent_loader: None,
+            listing_cache: ListingCache::new(),
+            virtual_listing: None,
             selection_info: None,
+            previewer: Previewer::new(tab_width),
+            preview_scroll: 0,
+            preview_column: 0,
+            soft_wrap: false,
+            preview_search_lines: 0,
+            pending_preview_search: None,
+            preview_matches: None,
+            tab_width,
 
             should_run: true,
             directory_changed: true,
             show_hidden: true,
+            hide_ignored: false,
+            ignore_rules: IgnoreRules::default(),
+            git_status: None,
+            git_loader: None,
+            git_refresh_pending: false,
+            git_view: GitView::Diff,
+            git_job: None,
+            trash_job: None,
+            commit_message: String::new(),
+            confirm_targets: Vec::new(),
+            confirm_not_shown: 0,
+            long_listing: false,
+            default_sort_order: SortOrder::default(),
+            sort_orders: HashMap::new(),
+            sort_key_loader: None,
+            filter: None,
+            prompt: None,
+            search: None,
+            finder: None,
+            grep_pattern: String::new(),
+            find_query: String::new(),
+            flatten: false,
+            flatten_depth: flatten::DEFAULT_FLATTEN_DEPTH,
+            flatten_files_only: false,
+            tree_view: false,
+            expanded: HashSet::new(),
+            respect_gitignore: true,
+            pending_selection: None,
+            sticky_filters: false,
+            saved_filters: HashMap::new(),
+            long_listing_fields: long_listing_presets[0].clone(),
+            long_listing_presets,
 
             keybindings: HashMap::new(),
+            screen: Screen::new(),
 
             new_events: Vec::new(),
             children: Vec::new(),
+            signals: None,
+            watcher: None,
 
             debug_messages: Vec::new(),
         })
     }
 
     pub fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        signals::install_panic_hook();
         let _ = self.setup_terminal();
         self.setup();
 
@@ -100,22 +258,31 @@ impl App {
         Ok(())
     }
 
-    fn setup_terminal(&self) -> anyhow::Result<()> {
+    fn setup_terminal(&mut self) -> anyhow::Result<()> {
         crossterm::terminal::enable_raw_mode()?;
-        let _ = crossterm::execute!(std::io::stdout(), crossterm::cursor::Hide);
-        println!("{}{}", ansi::CLEAR, ansi::RESET);
+        let _ = crossterm::execute!(
+            std::io::stdout(),
+            crossterm::terminal::EnterAlternateScreen,
+            crossterm::cursor::Hide
+        );
+        self.screen.invalidate();
         Ok(())
     }
 
     fn reset_terminal(&self) -> anyhow::Result<()> {
-        let _ = crossterm::execute!(std::io::stdout(), crossterm::cursor::Show);
-        crossterm::terminal::disable_raw_mode()?;
-        println!("{}{}", ansi::CLEAR, ansi::RESET);
-        Ok(())
+        restore_terminal()
     }
 
     fn setup(&mut self) {
         self.add_default_keybindings();
+        match signals::listen_for_signals() {
+            Ok(signals) => self.signals = Some(signals),
+            Err(err) => self.msg(format!("Unable to listen for signals: {}", err)),
+        }
+        match Watcher::new() {
+            Ok(watcher) => self.watcher = Some(watcher),
+            Err(err) => self.msg(format!("Unable to watch the filesystem: {}", err)),
+        }
     }
 
     fn msg(&mut self, message: impl AsRef<str>) {
@@ -126,6 +293,26 @@ impl App {
     }
 }
 
+/// The tab stop of text previews, set by KRANGER_TAB_WIDTH.
+fn tab_width() -> usize {
+    std::env::var("KRANGER_TAB_WIDTH")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|width| (1..=16).contains(width))
+        .unwrap_or(DEFAULT_TAB_WIDTH)
+}
+
+fn restore_terminal() -> anyhow::Result<()> {
+    let _ = crossterm::execute!(
+        std::io::stdout(),
+        crossterm::style::Print(ansi::RESET),
+        crossterm::cursor::Show,
+        crossterm::terminal::LeaveAlternateScreen
+    );
+    crossterm::terminal::disable_raw_mode()?;
+    Ok(())
+}
+
 #[derive(Debug, Clone, Copy)]
 enum ApplicationEvent {
     Close,
@@ -139,7 +326,58 @@ enum ApplicationEvent {
     OpenText,
     OpenExecutable,
     ToggleShowHidden,
+    ToggleHideIgnored,
+    CycleGitView,
+    Stage,
+    Unstage,
+    Discard,
+    StartCommit,
+    ScrollPreview(isize),
+    ScrollPreviewPages(isize),
+    ScrollPreviewSideways(isize),
+    ToggleSoftWrap,
     DebugEvent,
     ReadPdf,
     RunShellScript,
+    Suspend,
+    ToggleMark,
+    ToggleLongListing,
+    CycleLongListingFields,
+    CycleSortKey,
+    ToggleSortReverse,
+    ToggleDirectoriesFirst,
+    StartFilter,
+    ClearFilter,
+    CycleFilterMode,
+    ToggleStickyFilters,
+    StartSearch,
+    SearchNext,
+    SearchPrevious,
+    SearchPreview(bool),
+    StartFinder,
+    ToggleGitignore,
+    StartGrep,
+    StartFind,
+    Trash,
+    ToggleFlatten,
+    ToggleFlattenFilesOnly,
+    ChangeFlattenDepth(isize),
+    ToggleTreeView,
+    PromptPush(char),
+    PromptPop,
+    AcceptPrompt,
+    CancelPrompt,
+}
+
+/// What typed text goes into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Prompt {
+    Filter,
+    Search,
+    Finder,
+    Grep,
+    Find,
+    Commit,
+    ConfirmDiscard,
+    ConfirmTrash,
 }
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+/// `ls -l` style mode string, e.g. `drwxr-xr-x`.
+pub fn permissions_string(metadata: &std::fs::Metadata) -> String {
+    let mode = metadata.permissions().mode();
+    let file_type = metadata.file_type();
+    let kind = match mode & libc::S_IFMT {
+        libc::S_IFDIR => 'd',
+        libc::S_IFLNK => 'l',
+        libc::S_IFCHR => 'c',
+        libc::S_IFBLK => 'b',
+        libc::S_IFIFO => 'p',
+        libc::S_IFSOCK => 's',
+        _ if file_type.is_dir() => 'd',
+        _ => '-',
+    };
+    let mut output = String::with_capacity(10);
+    output.push(kind);
+    for (shift, special, special_char) in [
+        (6, libc::S_ISUID, 's'),
+        (3, libc::S_ISGID, 's'),
+        (0, libc::S_ISVTX, 't'),
+    ] {
+        let bits = (mode >> shift) & 0o7;
+        output.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        output.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        let executable = bits & 0o1 != 0;
+        output.push(match (mode & special != 0, executable) {
+            (true, true) => special_char,
+            (true, false) => special_char.to_ascii_uppercase(),
+            (false, true) => 'x',
+            (false, false) => '-',
+        });
+    }
+    output
+}
+
+/// Size with a binary unit suffix, e.g. `4.0K` or `1.2G`.
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["K", "M", "G", "T", "P", "E"];
+    if bytes < 1024 {
+        return format!("{bytes}B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if size < 10.0 {
+        format!("{size:.1}{}", UNITS[unit])
+    } else {
+        format!("{size:.0}{}", UNITS[unit])
+    }
+}
+
+/// Local time as `YYYY-MM-DD HH:MM`.
+pub fn format_time(time: SystemTime) -> String {
+    let seconds = match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as libc::time_t,
+        Err(err) => -(err.duration().as_secs() as libc::time_t),
+    };
+    // SAFETY: localtime_r only writes to the tm we pass in.
+    let tm = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&seconds, &mut tm).is_null() {
+            return String::from("?");
+        }
+        tm
+    };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min
+    )
+}
+
+pub fn owner_and_group(metadata: &std::fs::Metadata) -> String {
+    format!(
+        "{}:{}",
+        user_name(metadata.uid()),
+        group_name(metadata.gid())
+    )
+}
+
+/// Names of the users and groups looked up so far. The getpw* and getgr*
+/// functions share static buffers, so they are only called while holding
+/// the lock of the matching cache.
+static USER_NAMES: OnceLock<Mutex<HashMap<u32, String>>> = OnceLock::new();
+static GROUP_NAMES: OnceLock<Mutex<HashMap<u32, String>>> = OnceLock::new();
+
+/// Looked up once per uid, falling back to the number for unknown users.
+pub fn user_name(uid: u32) -> String {
+    let mut names = USER_NAMES.get_or_init(Default::default).lock().unwrap();
+    names
+        .entry(uid)
+        .or_insert_with(|| {
+            // SAFETY: the returned entry is copied out before any other
+            // getpw* call can overwrite it, and names are only looked up
+            // while holding the lock.
+            unsafe {
+                let passwd = libc::getpwuid(uid);
+                if passwd.is_null() {
+                    uid.to_string()
+                } else {
+                    CStr::from_ptr((*passwd).pw_name)
+                        .to_string_lossy()
+                        .into_owned()
+                }
+            }
+        })
+        .clone()
+}
+
+pub fn group_name(gid: u32) -> String {
+    let mut names = GROUP_NAMES.get_or_init(Default::default).lock().unwrap();
+    names
+        .entry(gid)
+        .or_insert_with(|| {
+            // SAFETY: see user_name.
+            unsafe {
+                let group = libc::getgrgid(gid);
+                if group.is_null() {
+                    gid.to_string()
+                } else {
+                    CStr::from_ptr((*group).gr_name)
+                        .to_string_lossy()
+                        .into_owned()
+                }
+            }
+        })
+        .clone()
+}
+
+/// The uid of the user called `name`, if there is one.
+pub fn user_id(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    let _names = USER_NAMES.get_or_init(Default::default).lock().unwrap();
+    // SAFETY: the uid is copied out of the entry right away, and the lock
+    // keeps user_name from overwriting it before that.
+    unsafe {
+        let passwd = libc::getpwnam(name.as_ptr());
+        (!passwd.is_null()).then(|| (*passwd).pw_uid)
+    }
+}
+
+pub fn group_id(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    let _names = GROUP_NAMES.get_or_init(Default::default).lock().unwrap();
+    // SAFETY: see user_id.
+    unsafe {
+        let group = libc::getgrnam(name.as_ptr());
+        (!group.is_null()).then(|| (*group).gr_gid)
+    }
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`.
+pub fn free_space(path: &Path) -> Option<u64> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: statvfs only writes to the struct we pass in.
+    unsafe {
+        let mut stat: libc::statvfs = std::mem::zeroed();
+        if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+            return None;
+        }
+        Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+}
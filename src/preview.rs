@@ -0,0 +1,263 @@
+use std::collections::{HashSet, VecDeque};
+use std::num::NonZeroUsize;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::SystemTime;
+
+use lru::LruCache;
+
+use crate::file::FileType;
+use crate::git::{git_info, GitPreview};
+use crate::info::{Info, InfoType, PreviewSize};
+
+const CACHE_SIZE: usize = 128;
+const MAX_WORKERS: usize = 4;
+
+/// A file to prefetch the preview of.
+pub type Neighbor = (PathBuf, FileType, Option<GitPreview>);
+
+/// Identifies a preview by what is asked for, without touching the file, so
+/// selecting an entry never waits on the disk.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PreviewKey {
+    pub path: PathBuf,
+    git: Option<GitPreview>,
+    /// Only text previews depend on the size, the others stay cached when
+    /// it changes.
+    size_read: Option<PreviewSize>,
+}
+
+impl PreviewKey {
+    pub fn new(path: &Path, ftype: FileType, git: Option<GitPreview>, size: PreviewSize) -> Self {
+        let size_read = (ftype == FileType::File && git.is_none() && InfoType::may_be_text(path))
+            .then_some(size);
+        Self {
+            path: path.to_path_buf(),
+            git,
+            size_read,
+        }
+    }
+}
+
+/// One version of a file, so a cached preview is rebuilt once the file is
+/// modified. Only ever read on the workers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Version {
+    mtime: Option<SystemTime>,
+    size: u64,
+}
+
+impl Version {
+    fn of(path: &Path) -> Self {
+        let metadata = std::fs::metadata(path).or_else(|_| std::fs::symlink_metadata(path));
+        Self {
+            mtime: metadata.as_ref().ok().and_then(|meta| meta.modified().ok()),
+            size: metadata.map(|meta| meta.len()).unwrap_or(0),
+        }
+    }
+}
+
+struct Job {
+    key: PreviewKey,
+    ftype: FileType,
+    tab_width: usize,
+    /// Version of the cached preview, which is kept if the file still is at it.
+    cached: Option<Version>,
+}
+
+/// A freshly built preview, or None if the cached one is still current.
+type Built = Option<(Version, Info)>;
+
+struct Queue {
+    jobs: Mutex<VecDeque<Job>>,
+    available: Condvar,
+    shutdown: AtomicBool,
+}
+
+/// Builds previews on a pool of worker threads and keeps the finished ones in
+/// an LRU cache. Only the selection and its neighbors are ever queued; moving
+/// the cursor drops whatever hasn't been started yet.
+pub struct Previewer {
+    queue: Arc<Queue>,
+    results: Receiver<(PreviewKey, Built)>,
+    cache: LruCache<PreviewKey, (Version, Arc<Info>)>,
+    /// Queued or running, so the same preview isn't built twice.
+    requested: HashSet<PreviewKey>,
+    wanted: Option<PreviewKey>,
+    tab_width: usize,
+    size: PreviewSize,
+}
+
+impl Previewer {
+    pub fn new(tab_width: usize) -> Self {
+        let queue = Arc::new(Queue {
+            jobs: Mutex::new(VecDeque::new()),
+            available: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+        });
+        let (sender, results) = channel();
+        let workers = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(2)
+            .clamp(2, MAX_WORKERS);
+        for _ in 0..workers {
+            let queue = Arc::clone(&queue);
+            let sender = sender.clone();
+            std::thread::spawn(move || worker(queue, sender));
+        }
+
+        Self {
+            queue,
+            results,
+            cache: LruCache::new(NonZeroUsize::new(CACHE_SIZE).unwrap()),
+            requested: HashSet::new(),
+            wanted: None,
+            tab_width,
+            size: PreviewSize::default(),
+        }
+    }
+
+    /// Previews are built to fill `size`. Returns whether it changed.
+    pub fn set_size(&mut self, size: PreviewSize) -> bool {
+        std::mem::replace(&mut self.size, size) != size
+    }
+
+    /// Returns the preview of `path` if it is cached, otherwise None while it
+    /// is built in the background. A cached preview is checked against the
+    /// file in the background too, and arrives again if it had to be rebuilt.
+    /// `neighbors` are prefetched.
+    pub fn request(
+        &mut self,
+        path: &Path,
+        ftype: FileType,
+        git: Option<GitPreview>,
+        neighbors: &[Neighbor],
+    ) -> Option<Arc<Info>> {
+        let key = PreviewKey::new(path, ftype, git, self.size);
+        self.wanted = Some(key.clone());
+        self.cancel_queued();
+
+        if let Some((version, info)) = self.cache.get(&key) {
+            let (version, info) = (*version, Arc::clone(info));
+            self.enqueue(key, ftype, Some(version));
+            self.prefetch(neighbors);
+            return Some(info);
+        }
+        self.enqueue(key, ftype, None);
+        self.prefetch(neighbors);
+        None
+    }
+
+    /// Collects finished previews. Returns the wanted one if it just arrived.
+    pub fn receive(&mut self) -> Option<Arc<Info>> {
+        let mut arrived = None;
+        while let Ok((key, built)) = self.results.try_recv() {
+            self.requested.remove(&key);
+            let Some((version, info)) = built else {
+                continue;
+            };
+            let info = Arc::new(info);
+            if self.wanted.as_ref() == Some(&key) {
+                arrived = Some(Arc::clone(&info));
+            }
+            self.cache.put(key, (version, info));
+        }
+        arrived
+    }
+
+    fn prefetch(&mut self, neighbors: &[Neighbor]) {
+        for (path, ftype, git) in neighbors {
+            let key = PreviewKey::new(path, *ftype, *git, self.size);
+            if !self.cache.contains(&key) {
+                self.enqueue(key, *ftype, None);
+            }
+        }
+    }
+
+    fn enqueue(&mut self, key: PreviewKey, ftype: FileType, cached: Option<Version>) {
+        if !self.requested.insert(key.clone()) {
+            return;
+        }
+        self.queue.jobs.lock().unwrap().push_back(Job {
+            key,
+            ftype,
+            tab_width: self.tab_width,
+            cached,
+        });
+        self.queue.available.notify_one();
+    }
+
+    fn cancel_queued(&mut self) {
+        for job in self.queue.jobs.lock().unwrap().drain(..) {
+            self.requested.remove(&job.key);
+        }
+    }
+}
+
+impl Drop for Previewer {
+    fn drop(&mut self) {
+        self.queue.shutdown.store(true, Ordering::Relaxed);
+        self.queue.available.notify_all();
+    }
+}
+
+/// Shown while the preview of `path` is built. Only its name is looked at,
+/// the contents are left for the workers to read.
+pub fn placeholder(path: &Path, ftype: FileType) -> Arc<Info> {
+    let info_type = match ftype {
+        FileType::Directory => InfoType::Directory,
+        FileType::Link => InfoType::Link,
+        _ => InfoType::from_name(path).unwrap_or(InfoType::Unknown),
+    };
+    Arc::new(Info::loading(info_type))
+}
+
+fn worker(queue: Arc<Queue>, sender: Sender<(PreviewKey, Built)>) {
+    loop {
+        let job = {
+            let mut jobs = queue.jobs.lock().unwrap();
+            loop {
+                if queue.shutdown.load(Ordering::Relaxed) {
+                    return;
+                }
+                if let Some(job) = jobs.pop_front() {
+                    break job;
+                }
+                jobs = queue.available.wait(jobs).unwrap();
+            }
+        };
+        let version = Version::of(&job.key.path);
+        if job.cached == Some(version) {
+            if sender.send((job.key, None)).is_err() {
+                return;
+            }
+            continue;
+        }
+        // A panic while building one preview must not take the worker down
+        // with it, or the preview would stay on "Loading…" forever.
+        let info = catch_unwind(AssertUnwindSafe(|| {
+            build_info(&job.key, job.ftype, job.tab_width)
+        }))
+        .unwrap_or_else(|_| Info::error("Unable to build the preview"));
+        if sender.send((job.key, Some((version, info)))).is_err() {
+            return;
+        }
+    }
+}
+
+fn build_info(key: &PreviewKey, ftype: FileType, tab_width: usize) -> Info {
+    if let Some(info) = key.git.and_then(|git| git_info(&key.path, git, tab_width)) {
+        return info;
+    }
+    let path = key.path.clone();
+    match ftype {
+        FileType::File => Info::new(&path, tab_width, key.size_read.unwrap_or_default())
+            .unwrap_or_else(|err| Info::error(format!("Unable to preview: {err}"))),
+        FileType::Directory => Info::directory(&path),
+        FileType::Link => Info::link(&path),
+        FileType::Unknown => Info::with_lines(InfoType::Unknown, Vec::new()),
+    }
+}
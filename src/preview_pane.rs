@@ -0,0 +1,148 @@
+use std::ops::Range;
+
+use crate::info::Info;
+use crate::width::{grapheme_width, pieces, Piece};
+use crate::App;
+
+/// Columns moved per horizontal scroll.
+const HORIZONTAL_STEP: usize = 8;
+
+/// A row of the preview, with the byte ranges of its search matches.
+pub type PreviewRow = (String, Vec<Range<usize>>);
+
+/// Appends `grapheme` to `row`, extending the last match if it is in one.
+fn push_grapheme(row: &mut PreviewRow, grapheme: &str, in_match: bool) {
+    let (text, matches) = row;
+    let start = text.len();
+    text.push_str(grapheme);
+    if !in_match {
+        return;
+    }
+    match matches.last_mut() {
+        Some(last) if last.end == start => last.end = text.len(),
+        _ => matches.push(start..text.len()),
+    }
+}
+
+/// Splits `line` into parts at most `width` columns wide. Colors carry over
+/// to the following parts, and so do the `matches` found in the whole line.
+fn wrap_line(line: &str, matches: &[Range<usize>], width: usize) -> Vec<PreviewRow> {
+    let mut parts = Vec::new();
+    let mut part = (String::new(), Vec::new());
+    let mut used = 0;
+    // The escape sequences in effect, repeated at the start of every part.
+    let mut colors = String::new();
+    for (offset, piece) in pieces(line) {
+        match piece {
+            Piece::Escape(escape) => {
+                part.0.push_str(escape);
+                colors.push_str(escape);
+            }
+            Piece::Text(grapheme) => {
+                let grapheme_width = grapheme_width(grapheme);
+                if used + grapheme_width > width && used > 0 {
+                    parts.push(std::mem::replace(&mut part, (colors.clone(), Vec::new())));
+                    used = 0;
+                }
+                let in_match = matches.iter().any(|range| range.contains(&offset));
+                push_grapheme(&mut part, grapheme, in_match);
+                used += grapheme_width;
+            }
+        }
+    }
+    parts.push(part);
+    parts
+}
+
+/// `line` without its first `columns` columns, keeping its colors and the
+/// `matches` found in the whole line.
+fn skip_columns(line: &str, matches: &[Range<usize>], columns: usize) -> PreviewRow {
+    let mut row = (String::with_capacity(line.len()), Vec::new());
+    let mut skipped = 0;
+    for (offset, piece) in pieces(line) {
+        match piece {
+            Piece::Escape(escape) => row.0.push_str(escape),
+            Piece::Text(grapheme) if skipped < columns => skipped += grapheme_width(grapheme),
+            Piece::Text(grapheme) => {
+                let in_match = matches.iter().any(|range| range.contains(&offset));
+                push_grapheme(&mut row, grapheme, in_match);
+            }
+        }
+    }
+    row
+}
+
+impl App {
+    /// The preview lines from the scroll position on, wrapped or moved
+    /// sideways to fit `width`. Matches are found before, so the part of a
+    /// line scrolled out of view doesn't change what matches.
+    pub fn preview_rows(&self, lines: &[String], width: usize, rows: usize) -> Vec<PreviewRow> {
+        let lines = lines.iter().skip(self.preview_scroll);
+        if self.soft_wrap {
+            lines
+                .flat_map(|line| wrap_line(line, &self.preview_highlights(line), width))
+                .take(rows)
+                .collect()
+        } else {
+            lines
+                .take(rows)
+                .map(|line| skip_columns(line, &self.preview_highlights(line), self.preview_column))
+                .collect()
+        }
+    }
+
+    /// Rows the preview shows at once.
+    pub fn preview_page(&self) -> usize {
+        (self.height - 2)
+            .saturating_sub(self.debug_messages.len())
+            .max(1)
+    }
+
+    pub fn preview_lines(&self) -> &[String] {
+        self.selection_info
+            .as_deref()
+            .map(Info::lines)
+            .unwrap_or_default()
+    }
+
+    pub fn scroll_preview(&mut self, change_by: isize) {
+        let last_line = self.preview_lines().len().saturating_sub(1);
+        self.preview_scroll = self
+            .preview_scroll
+            .saturating_add_signed(change_by)
+            .min(last_line);
+        self.resize_preview();
+    }
+
+    pub fn scroll_preview_pages(&mut self, pages: isize) {
+        self.scroll_preview(pages * self.preview_page() as isize);
+    }
+
+    pub fn scroll_preview_sideways(&mut self, steps: isize) {
+        if self.soft_wrap {
+            return;
+        }
+        let widest = self.selection_info.as_ref().map_or(0, |info| info.widest());
+        self.preview_column = self
+            .preview_column
+            .saturating_add_signed(steps * HORIZONTAL_STEP as isize)
+            .min(widest.saturating_sub(1));
+        self.resize_preview();
+    }
+
+    pub fn toggle_soft_wrap(&mut self) {
+        self.soft_wrap = !self.soft_wrap;
+        self.msg(match self.soft_wrap {
+            true => "Wrapping long lines in the preview",
+            false => "Cutting long lines in the preview",
+        });
+        self.resize_preview();
+    }
+
+    /// Reads more of the file once the preview scrolls past what was read.
+    pub fn resize_preview(&mut self) {
+        if self.previewer.set_size(self.preview_size()) {
+            self.update_selected_item();
+        }
+    }
+}
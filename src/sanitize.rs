@@ -0,0 +1,86 @@
+use std::borrow::Cow;
+use std::ffi::OsStr;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::width::{display_width, grapheme_width};
+
+/// Makes file contents safe to print: tabs are expanded to `tab_width` and
+/// control characters are replaced by visible placeholders.
+pub fn sanitize_text(input: &str, tab_width: usize) -> String {
+    sanitize(input, Some(tab_width))
+}
+
+/// Like `sanitize_text`, but a tab in a file name is shown as `␉` so the name
+/// stays in one piece.
+pub fn sanitize_name(input: &str) -> String {
+    sanitize(input, None)
+}
+
+fn sanitize(input: &str, tab_width: Option<usize>) -> String {
+    if !input.chars().any(needs_escape) {
+        return input.to_owned();
+    }
+    let mut output = String::with_capacity(input.len());
+    let mut column = 0;
+    for grapheme in input.graphemes(true) {
+        if let (Some(tab_width), "\t") = (tab_width, grapheme) {
+            let spaces = tab_width - column % tab_width.max(1);
+            output.extend(std::iter::repeat_n(' ', spaces));
+            column += spaces;
+        } else if grapheme.chars().any(needs_escape) {
+            for ch in grapheme.chars() {
+                let start = output.len();
+                if needs_escape(ch) {
+                    push_placeholder(&mut output, ch);
+                } else {
+                    output.push(ch);
+                }
+                column += display_width(&output[start..]);
+            }
+        } else {
+            output.push_str(grapheme);
+            column += grapheme_width(grapheme);
+        }
+    }
+    output
+}
+
+fn needs_escape(ch: char) -> bool {
+    ch.is_control() || is_bidi_control(ch)
+}
+
+/// Characters that reorder the text around them, which can make a name look
+/// like something it isn't.
+fn is_bidi_control(ch: char) -> bool {
+    matches!(
+        ch,
+        '\u{061C}' | '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}'
+    )
+}
+
+/// C0 controls and DEL use the Unicode control pictures (`␛` for escape),
+/// everything else is written as a Rust-style `\u{..}` escape.
+fn push_placeholder(output: &mut String, ch: char) {
+    match ch {
+        '\0'..='\x1F' => output.push(char::from_u32(0x2400 + ch as u32).unwrap_or('?')),
+        '\x7F' => output.push('␡'),
+        _ => output.push_str(&format!("\\u{{{:x}}}", ch as u32)),
+    }
+}
+
+/// Decodes a name for display. Bytes that aren't valid UTF-8 are written as
+/// `\xNN`, so names that differ only in those bytes still look different.
+pub fn escape_invalid_utf8(name: &OsStr) -> Cow<'_, str> {
+    if let Some(name) = name.to_str() {
+        return Cow::Borrowed(name);
+    }
+    let mut output = String::new();
+    for chunk in name.as_encoded_bytes().utf8_chunks() {
+        output.push_str(chunk.valid());
+        for byte in chunk.invalid() {
+            output.push_str(&format!("\\x{byte:02x}"));
+        }
+    }
+    Cow::Owned(output)
+}
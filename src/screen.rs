@@ -0,0 +1,388 @@
+use std::fmt::Write as _;
+use std::io::Write;
+
+use crate::ansi;
+use crate::width::grapheme_width;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+// Unchanged cells between two changed spans are rewritten instead of moving
+// the cursor when the gap is at most this wide, since a move costs ~6 bytes.
+const MAX_GAP: usize = 6;
+
+const SYNC_BEGIN: &str = "\x1B[?2026h";
+const SYNC_END: &str = "\x1B[?2026l";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Color {
+    #[default]
+    Default,
+    /// One of the 16 basic colors, 0-7 normal and 8-15 bright.
+    Ansi(u8),
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Style {
+    pub fg: Color,
+    pub bg: Color,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+}
+
+impl Style {
+    /// Applies the parameters of a `CSI ... m` sequence.
+    pub fn apply_sgr(&mut self, params: &str) {
+        let mut params = params
+            .split(';')
+            .map(|param| param.parse::<u8>().unwrap_or(0));
+        while let Some(param) = params.next() {
+            match param {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                7 => self.reverse = true,
+                22 => {
+                    self.bold = false;
+                    self.dim = false;
+                }
+                23 => self.italic = false,
+                24 => self.underline = false,
+                27 => self.reverse = false,
+                30..=37 => self.fg = Color::Ansi(param - 30),
+                39 => self.fg = Color::Default,
+                40..=47 => self.bg = Color::Ansi(param - 40),
+                49 => self.bg = Color::Default,
+                90..=97 => self.fg = Color::Ansi(param - 90 + 8),
+                100..=107 => self.bg = Color::Ansi(param - 100 + 8),
+                38 | 48 => {
+                    let color = match params.next() {
+                        Some(5) => Color::Indexed(params.next().unwrap_or(0)),
+                        Some(2) => Color::Rgb(
+                            params.next().unwrap_or(0),
+                            params.next().unwrap_or(0),
+                            params.next().unwrap_or(0),
+                        ),
+                        _ => Color::Default,
+                    };
+                    if param == 38 {
+                        self.fg = color;
+                    } else {
+                        self.bg = color;
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn has_attributes_missing_in(&self, other: &Style) -> bool {
+        (self.bold && !other.bold)
+            || (self.dim && !other.dim)
+            || (self.italic && !other.italic)
+            || (self.underline && !other.underline)
+            || (self.reverse && !other.reverse)
+    }
+
+    /// The shortest SGR sequence that turns `self` into `to`.
+    fn transition(&self, to: &Style) -> String {
+        if self == to {
+            return String::new();
+        }
+        let mut params = Vec::new();
+        let from = if self.has_attributes_missing_in(to) {
+            params.push("0".to_string());
+            Style::default()
+        } else {
+            *self
+        };
+        for (enabled, was_enabled, code) in [
+            (to.bold, from.bold, "1"),
+            (to.dim, from.dim, "2"),
+            (to.italic, from.italic, "3"),
+            (to.underline, from.underline, "4"),
+            (to.reverse, from.reverse, "7"),
+        ] {
+            if enabled && !was_enabled {
+                params.push(code.to_string());
+            }
+        }
+        if to.fg != from.fg {
+            params.push(color_sgr(to.fg, false));
+        }
+        if to.bg != from.bg {
+            params.push(color_sgr(to.bg, true));
+        }
+        format!("\x1B[{}m", params.join(";"))
+    }
+}
+
+fn color_sgr(color: Color, background: bool) -> String {
+    let offset = if background { 10 } else { 0 };
+    match color {
+        Color::Default => format!("{}", 39 + offset),
+        Color::Ansi(n) if n < 8 => format!("{}", 30 + offset + n),
+        Color::Ansi(n) => format!("{}", 90 + offset + n - 8),
+        Color::Indexed(n) => format!("{};5;{n}", 38 + offset),
+        Color::Rgb(r, g, b) => format!("{};2;{r};{g};{b}", 38 + offset),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Cell {
+    /// Empty for the right half of a wide character.
+    symbol: String,
+    width: u8,
+    style: Style,
+}
+
+impl Cell {
+    fn blank() -> Self {
+        Self {
+            symbol: String::from(" "),
+            width: 1,
+            style: Style::default(),
+        }
+    }
+
+    fn is_continuation(&self) -> bool {
+        self.width == 0
+    }
+}
+
+/// A double-buffered grid of cells. Each frame is drawn into the back buffer
+/// and only the cells that differ from the last frame are sent to the terminal.
+pub struct Screen {
+    width: usize,
+    height: usize,
+    current: Vec<Cell>,
+    previous: Vec<Cell>,
+    invalidated: bool,
+    synchronized_update: bool,
+}
+
+impl Screen {
+    pub fn new() -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            current: Vec::new(),
+            previous: Vec::new(),
+            invalidated: true,
+            synchronized_update: supports_synchronized_update(),
+        }
+    }
+
+    pub fn resize(&mut self, width: usize, height: usize) {
+        if width == self.width && height == self.height {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        self.current = vec![Cell::blank(); width * height];
+        self.previous = vec![Cell::blank(); width * height];
+        self.invalidate();
+    }
+
+    /// Forgets what is on the terminal, so the next frame is drawn in full.
+    pub fn invalidate(&mut self) {
+        self.invalidated = true;
+    }
+
+    /// Draws a line of text that may contain SGR escape sequences, clipped to
+    /// the screen width.
+    pub fn draw_line(&mut self, row: usize, line: &str) {
+        if row >= self.height {
+            return;
+        }
+        let mut col = 0;
+        let mut style = Style::default();
+        let mut rest = line;
+        while !rest.is_empty() {
+            let text_end = rest.find('\x1B').unwrap_or(rest.len());
+            let (text, escape) = rest.split_at(text_end);
+            if !self.draw_text(row, &mut col, text, style) {
+                return;
+            }
+            rest = self.parse_escape(escape, &mut style);
+        }
+    }
+
+    /// Returns false once the end of the row is reached.
+    fn draw_text(&mut self, row: usize, col: &mut usize, text: &str, style: Style) -> bool {
+        let row_start = row * self.width;
+        for grapheme in text.graphemes(true) {
+            if grapheme.chars().all(char::is_control) {
+                continue;
+            }
+            let width = grapheme_width(grapheme);
+            if width == 0 {
+                // A lone zero-width cluster belongs to whatever precedes it.
+                if let Some(previous) = self.current[row_start..row_start + *col]
+                    .iter_mut()
+                    .rev()
+                    .find(|cell| !cell.is_continuation())
+                {
+                    previous.symbol.push_str(grapheme);
+                }
+                continue;
+            }
+            if *col + width > self.width {
+                return false;
+            }
+            let cell = &mut self.current[row_start + *col];
+            cell.symbol.clear();
+            cell.symbol.push_str(grapheme);
+            cell.width = width as u8;
+            cell.style = style;
+            if width == 2 {
+                let continuation = &mut self.current[row_start + *col + 1];
+                continuation.symbol.clear();
+                continuation.width = 0;
+                continuation.style = style;
+            }
+            *col += width;
+        }
+        true
+    }
+
+    /// Applies an escape sequence at the start of `input` and returns what
+    /// follows it. Anything other than SGR is dropped.
+    fn parse_escape<'a>(&self, input: &'a str, style: &mut Style) -> &'a str {
+        let Some(sequence) = input.strip_prefix("\x1B[") else {
+            return input.get(1..).unwrap_or_default();
+        };
+        match sequence.find(|ch: char| ('\x40'..='\x7E').contains(&ch)) {
+            Some(end) => {
+                if sequence[end..].starts_with('m') {
+                    style.apply_sgr(&sequence[..end]);
+                }
+                &sequence[end + 1..]
+            }
+            None => "",
+        }
+    }
+
+    /// Sends the differences between this frame and the last one to the
+    /// terminal, then starts a new blank frame.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        let mut output = String::new();
+        if self.synchronized_update {
+            output.push_str(SYNC_BEGIN);
+        }
+        if self.invalidated {
+            // The terminal is blank after clearing, so diff against a blank frame.
+            output.push_str(ansi::RESET);
+            output.push_str(ansi::CLEAR);
+            self.previous.fill(Cell::blank());
+        }
+
+        let mut cursor: Option<(usize, usize)> = None;
+        let mut style = Style::default();
+        for row in 0..self.height {
+            for (start, end) in self.changed_spans(row) {
+                self.write_span(&mut output, row, start, end, &mut cursor, &mut style);
+            }
+        }
+        output.push_str(ansi::RESET);
+        if self.synchronized_update {
+            output.push_str(SYNC_END);
+        }
+
+        if self.invalidated || cursor.is_some() {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(output.as_bytes())?;
+            stdout.flush()?;
+        }
+
+        self.invalidated = false;
+        std::mem::swap(&mut self.current, &mut self.previous);
+        for cell in &mut self.current {
+            cell.symbol.clear();
+            cell.symbol.push(' ');
+            cell.width = 1;
+            cell.style = Style::default();
+        }
+        Ok(())
+    }
+
+    /// Column ranges of `row` that need to be rewritten, with small gaps merged.
+    fn changed_spans(&self, row: usize) -> Vec<(usize, usize)> {
+        let row_start = row * self.width;
+        let mut spans: Vec<(usize, usize)> = Vec::new();
+        for col in 0..self.width {
+            let index = row_start + col;
+            if self.current[index] == self.previous[index] {
+                continue;
+            }
+            let mut start = col;
+            if self.current[index].is_continuation() && col > 0 {
+                start -= 1;
+            }
+            match spans.last_mut() {
+                Some((_, end)) if start <= *end + MAX_GAP => *end = col + 1,
+                _ => spans.push((start, col + 1)),
+            }
+        }
+        spans
+    }
+
+    fn write_span(
+        &self,
+        output: &mut String,
+        row: usize,
+        start: usize,
+        end: usize,
+        cursor: &mut Option<(usize, usize)>,
+        style: &mut Style,
+    ) {
+        match *cursor {
+            Some((cursor_row, cursor_col)) if cursor_row == row && cursor_col == start => (),
+            Some((cursor_row, cursor_col)) if cursor_row == row && cursor_col < start => {
+                let _ = write!(output, "\x1B[{}C", start - cursor_col);
+            }
+            _ => {
+                let _ = write!(output, "\x1B[{};{}H", row + 1, start + 1);
+            }
+        }
+        let mut col = start;
+        for cell in &self.current[row * self.width + start..row * self.width + end] {
+            if cell.is_continuation() {
+                continue;
+            }
+            output.push_str(&style.transition(&cell.style));
+            *style = cell.style;
+            output.push_str(&cell.symbol);
+            col += cell.width as usize;
+        }
+        *cursor = Some((row, col));
+    }
+}
+
+/// Terminals that don't know mode 2026 should ignore it, but a few old ones
+/// print garbage, so it is only enabled for terminals known to handle it.
+fn supports_synchronized_update() -> bool {
+    if let Ok(value) = std::env::var("KRANGER_SYNC_UPDATE") {
+        return value != "0";
+    }
+    let term = std::env::var("TERM").unwrap_or_default();
+    let term_program = std::env::var("TERM_PROGRAM").unwrap_or_default();
+    [
+        "kitty",
+        "foot",
+        "alacritty",
+        "wezterm",
+        "contour",
+        "ghostty",
+        "tmux",
+    ]
+    .iter()
+    .any(|known| term.contains(known) || term_program.to_lowercase().contains(known))
+        || term_program == "iTerm.app"
+}
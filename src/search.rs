@@ -0,0 +1,239 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use regex::{Regex, RegexBuilder};
+
+use crate::case::smart_case;
+use crate::file::File;
+use crate::info::{Info, InfoType};
+use crate::sanitize::sanitize_name;
+use crate::{App, Prompt};
+
+/// How far into a file a preview search reads.
+const MAX_SEARCHED_LINES: usize = 1 << 17;
+
+pub struct Search {
+    pub pattern: String,
+    /// None while the pattern is empty or not a valid regex.
+    pub regex: Option<Regex>,
+    /// Where the cursor was when typing started, matches are looked for from
+    /// here as the pattern changes.
+    origin: usize,
+}
+
+impl Search {
+    /// Byte ranges of all matches in `text`.
+    pub fn find(&self, text: &str) -> Vec<Range<usize>> {
+        match &self.regex {
+            Some(regex) => regex.find_iter(text).map(|m| m.range()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn matches(&self, file: &File) -> bool {
+        self.regex
+            .as_ref()
+            .is_some_and(|regex| regex.is_match(&sanitize_name(&file.display_name())))
+    }
+}
+
+/// How many lines of a preview have a match of a pattern.
+pub struct PreviewMatches {
+    info: Arc<Info>,
+    pattern: String,
+    pub count: usize,
+}
+
+/// Ignores case unless the pattern has an uppercase letter.
+pub fn smart_case_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(smart_case(pattern))
+        .build()
+}
+
+fn compile(pattern: &str) -> Option<Regex> {
+    if pattern.is_empty() {
+        return None;
+    }
+    smart_case_regex(pattern).ok()
+}
+
+impl App {
+    pub fn start_search(&mut self) {
+        self.prompt = Some(Prompt::Search);
+        self.search = Some(Search {
+            pattern: String::new(),
+            regex: None,
+            origin: self.current_selection,
+        });
+    }
+
+    /// Changes the pattern and moves the cursor to the first match at or
+    /// after where the search started.
+    pub fn edit_search(&mut self, edit: impl FnOnce(&mut String)) {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        edit(&mut search.pattern);
+        search.regex = compile(&search.pattern);
+        let origin = search.origin;
+        let target = self.find_match(origin, true).unwrap_or(origin);
+        self.select(target);
+    }
+
+    pub fn accept_search(&mut self) {
+        self.prompt = None;
+        if self
+            .search
+            .as_ref()
+            .is_some_and(|search| search.regex.is_none())
+        {
+            self.search = None;
+        }
+    }
+
+    /// Drops the search and puts the cursor back where it was.
+    pub fn cancel_search(&mut self) {
+        self.prompt = None;
+        if let Some(search) = self.search.take() {
+            self.select(search.origin);
+        }
+    }
+
+    /// Jumps to the next or previous matching entry, wrapping around.
+    pub fn search_next(&mut self, forward: bool) -> Result<()> {
+        if self
+            .search
+            .as_ref()
+            .is_none_or(|search| search.regex.is_none())
+        {
+            return Err(anyhow!("No search pattern"));
+        }
+        let len = self.current_directory_contents.len();
+        let start = match forward {
+            true => self.current_selection + 1,
+            false => self.current_selection + len.saturating_sub(1),
+        };
+        let target = self
+            .find_match(start, forward)
+            .ok_or(anyhow!("No matches"))?;
+        self.select(target);
+        Ok(())
+    }
+
+    /// Scrolls the preview to the next or previous line with a match,
+    /// wrapping around. A text preview is read further before the search
+    /// wraps around what was read so far.
+    pub fn search_preview(&mut self, forward: bool) -> Result<()> {
+        self.pending_preview_search = None;
+        if self
+            .search
+            .as_ref()
+            .is_none_or(|search| search.regex.is_none())
+        {
+            return Err(anyhow!("No search pattern"));
+        }
+        let info = self.selection_info.clone();
+        let lines = info.as_deref().map(Info::lines).unwrap_or_default();
+        let len = lines.len();
+        let matches = |index: &usize| !self.preview_highlights(&lines[*index]).is_empty();
+        let further = match forward {
+            true => (self.preview_scroll + 1..len).find(matches),
+            false => (0..self.preview_scroll.min(len)).rev().find(matches),
+        };
+        if further.is_none() && self.preview_is_cut(len) {
+            self.preview_search_lines = (len * 2).min(MAX_SEARCHED_LINES);
+            self.pending_preview_search = self.selection_info.clone().map(|info| (forward, info));
+            self.resize_preview();
+            return Ok(());
+        }
+        let start = match forward {
+            true => self.preview_scroll + 1,
+            false => self.preview_scroll + len.saturating_sub(1),
+        };
+        let target = further
+            .or_else(|| {
+                (0..len)
+                    .map(|step| match forward {
+                        true => (start + step) % len,
+                        false => (start + len - step) % len,
+                    })
+                    .find(matches)
+            })
+            .ok_or(anyhow!("No matches in the preview"))?;
+        self.scroll_preview(target as isize - self.preview_scroll as isize);
+        Ok(())
+    }
+
+    /// Counts the preview lines with a match again if the preview or the
+    /// pattern changed, rather than on every frame.
+    pub fn count_preview_matches(&mut self) {
+        let (Some(search), Some(info)) = (&self.search, &self.selection_info) else {
+            self.preview_matches = None;
+            return;
+        };
+        if self.preview_matches.as_ref().is_some_and(|matches| {
+            Arc::ptr_eq(&matches.info, info) && matches.pattern == search.pattern
+        }) {
+            return;
+        }
+        let count = info
+            .lines()
+            .iter()
+            .filter(|line| !self.preview_highlights(line).is_empty())
+            .count();
+        self.preview_matches = Some(PreviewMatches {
+            info: Arc::clone(info),
+            pattern: search.pattern.clone(),
+            count,
+        });
+    }
+
+    /// Whether a text preview of `len` lines stops before its file does.
+    fn preview_is_cut(&self, len: usize) -> bool {
+        self.selection_info
+            .as_ref()
+            .is_some_and(|info| matches!(info.info_type, InfoType::Text | InfoType::ShellScript))
+            && len >= self.preview_size().lines
+            && len < MAX_SEARCHED_LINES
+    }
+
+    /// Searches on once more of the file is read.
+    pub fn continue_preview_search(&mut self) {
+        let Some((forward, searched)) = &self.pending_preview_search else {
+            return;
+        };
+        if self
+            .selection_info
+            .as_ref()
+            .is_some_and(|info| Arc::ptr_eq(info, searched))
+        {
+            return;
+        }
+        if let Err(err) = self.search_preview(*forward) {
+            self.msg(format!("Error: {}", err));
+        }
+    }
+
+    /// Index of the first matching entry starting at `start` and going in
+    /// the given direction, wrapping around the end of the listing.
+    fn find_match(&self, start: usize, forward: bool) -> Option<usize> {
+        let search = self.search.as_ref()?;
+        let len = self.current_directory_contents.len();
+        (0..len)
+            .map(|step| match forward {
+                true => (start + step) % len,
+                false => (start + len - step) % len,
+            })
+            .find(|index| search.matches(&self.current_directory_contents[*index]))
+    }
+
+    fn select(&mut self, index: usize) {
+        if index != self.current_selection {
+            self.current_selection = index;
+            self.clamp_selection();
+            self.update_selected_item();
+        }
+    }
+}
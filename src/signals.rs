@@ -0,0 +1,53 @@
+use crate::App;
+
+use anyhow::Result;
+use signal_hook::consts::{SIGCONT, SIGHUP, SIGTERM, SIGTSTP};
+use signal_hook::iterator::Signals;
+
+pub fn listen_for_signals() -> Result<Signals> {
+    Ok(Signals::new([SIGTERM, SIGHUP, SIGTSTP, SIGCONT])?)
+}
+
+/// Restores the terminal before the default hook prints the panic message,
+/// otherwise it ends up on the alternate screen in raw mode. Panics on other
+/// threads leave the terminal alone, since the UI keeps running.
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |panic_info| {
+        if std::thread::current().name() == Some("main") {
+            let _ = crate::restore_terminal();
+        }
+        default_hook(panic_info);
+    }));
+}
+
+impl App {
+    pub fn handle_signals(&mut self) {
+        let pending: Vec<i32> = match &mut self.signals {
+            Some(signals) => signals.pending().collect(),
+            None => return,
+        };
+        for signal in pending {
+            let result = match signal {
+                SIGTERM | SIGHUP => {
+                    self.should_run = false;
+                    Ok(())
+                }
+                SIGTSTP => self.suspend(),
+                SIGCONT => self.setup_terminal(),
+                _ => Ok(()),
+            };
+            if let Err(err) = result {
+                self.msg(format!("Error: {}", err));
+            }
+        }
+    }
+
+    /// Gives the terminal back to the shell and stops the process until it
+    /// receives SIGCONT.
+    pub fn suspend(&mut self) -> Result<()> {
+        self.reset_terminal()?;
+        signal_hook::low_level::emulate_default_handler(SIGTSTP)?;
+        self.setup_terminal()
+    }
+}
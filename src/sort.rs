@@ -0,0 +1,358 @@
+use std::cmp::Ordering;
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::sync::Arc;
+
+use crate::file::{File, FileType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    /// Names with digit runs compared as numbers, so `file2` < `file10`.
+    Natural,
+    CaseInsensitive,
+    Size,
+    Modified,
+    Changed,
+    Extension,
+    Type,
+    Random,
+}
+
+impl SortKey {
+    pub fn next(self) -> Self {
+        match self {
+            SortKey::Natural => SortKey::CaseInsensitive,
+            SortKey::CaseInsensitive => SortKey::Size,
+            SortKey::Size => SortKey::Modified,
+            SortKey::Modified => SortKey::Changed,
+            SortKey::Changed => SortKey::Extension,
+            SortKey::Extension => SortKey::Type,
+            SortKey::Type => SortKey::Random,
+            SortKey::Random => SortKey::Natural,
+        }
+    }
+
+    /// Whether comparing by the key needs a stat or a read of every entry.
+    fn reads_disk(self) -> bool {
+        matches!(
+            self,
+            SortKey::Size | SortKey::Modified | SortKey::Changed | SortKey::Type
+        )
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SortKey::Natural => "natural",
+            SortKey::CaseInsensitive => "name",
+            SortKey::Size => "size",
+            SortKey::Modified => "mtime",
+            SortKey::Changed => "ctime",
+            SortKey::Extension => "extension",
+            SortKey::Type => "type",
+            SortKey::Random => "random",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortOrder {
+    pub key: SortKey,
+    pub reverse: bool,
+    pub directories_first: bool,
+    /// Keeps a random order stable while the listing is updated.
+    seed: u64,
+    /// Names are paths relative to the directory, compared level by level
+    /// so entries stay below their parent like in a tree.
+    tree: bool,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        Self {
+            key: SortKey::Natural,
+            reverse: false,
+            directories_first: true,
+            seed: 0,
+            tree: false,
+        }
+    }
+}
+
+impl SortOrder {
+    pub fn with_key(self, key: SortKey) -> Self {
+        let seed = match key {
+            SortKey::Random => RandomState::new().hash_one(0u8),
+            _ => 0,
+        };
+        Self { key, seed, ..self }
+    }
+
+    pub fn reversed(self) -> Self {
+        Self {
+            reverse: !self.reverse,
+            ..self
+        }
+    }
+
+    pub fn toggle_directories_first(self) -> Self {
+        Self {
+            directories_first: !self.directories_first,
+            ..self
+        }
+    }
+
+    pub fn in_tree(self, tree: bool) -> Self {
+        Self { tree, ..self }
+    }
+
+    pub fn describe(&self) -> String {
+        let mut description = String::from(self.key.name());
+        if self.reverse {
+            description.push_str(" reversed");
+        }
+        description
+    }
+
+    pub fn reads_disk(&self) -> bool {
+        self.key.reads_disk()
+    }
+
+    /// Compares two entries of `directory`. Entries only compare equal when
+    /// they have the same name and type.
+    pub fn compare(&self, f1: &File, f2: &File, directory: &Path) -> Ordering {
+        if self.tree {
+            return self.compare_in_tree(f1, f2, directory);
+        }
+        if self.directories_first {
+            let dir1 = f1.ftype == FileType::Directory;
+            let dir2 = f2.ftype == FileType::Directory;
+            if dir1 != dir2 {
+                return dir2.cmp(&dir1);
+            }
+        }
+        let ordering = self
+            .compare_by_key(f1, f2, directory)
+            .then_with(|| natural_cmp(f1.name.as_encoded_bytes(), f2.name.as_encoded_bytes()))
+            .then_with(|| f1.name.cmp(&f2.name))
+            .then_with(|| (f1.ftype as u8).cmp(&(f2.ftype as u8)));
+        if self.reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+
+    /// Compares the first level where the paths differ, a directory always
+    /// comes before what is inside of it.
+    fn compare_in_tree(&self, f1: &File, f2: &File, directory: &Path) -> Ordering {
+        let flat = self.in_tree(false);
+        let components1: Vec<_> = Path::new(&f1.name).components().collect();
+        let components2: Vec<_> = Path::new(&f2.name).components().collect();
+        let Some(level) = components1
+            .iter()
+            .zip(&components2)
+            .position(|(c1, c2)| c1 != c2)
+        else {
+            return components1
+                .len()
+                .cmp(&components2.len())
+                .then_with(|| flat.compare(f1, f2, directory));
+        };
+        // The differing ancestors, which are directories unless they are the
+        // entries themselves.
+        let ancestor = |components: &[Component]| {
+            if level + 1 == components.len() {
+                None
+            } else {
+                let path: PathBuf = components[..=level].iter().collect();
+                Some(File::new(FileType::Directory, path.into_os_string()))
+            }
+        };
+        let a1 = ancestor(&components1);
+        let a2 = ancestor(&components2);
+        flat.compare(
+            a1.as_ref().unwrap_or(f1),
+            a2.as_ref().unwrap_or(f2),
+            directory,
+        )
+    }
+
+    fn compare_by_key(&self, f1: &File, f2: &File, directory: &Path) -> Ordering {
+        match self.key {
+            SortKey::Natural => Ordering::Equal,
+            SortKey::CaseInsensitive => {
+                let name1 = f1.name.to_string_lossy().to_lowercase();
+                let name2 = f2.name.to_string_lossy().to_lowercase();
+                name1.cmp(&name2)
+            }
+            SortKey::Size => {
+                let size = |file: &File| file.metadata(directory).map(|meta| meta.len());
+                size(f1).cmp(&size(f2))
+            }
+            SortKey::Modified => {
+                let mtime = |file: &File| file.metadata(directory).and_then(|m| m.modified().ok());
+                mtime(f1).cmp(&mtime(f2))
+            }
+            SortKey::Changed => {
+                let ctime = |file: &File| {
+                    file.metadata(directory)
+                        .map(|meta| (meta.ctime(), meta.ctime_nsec()))
+                };
+                ctime(f1).cmp(&ctime(f2))
+            }
+            SortKey::Extension => extension(f1).cmp(extension(f2)),
+            SortKey::Type => (f1.info_type(directory) as u8).cmp(&(f2.info_type(directory) as u8)),
+            SortKey::Random => self.random_rank(f1).cmp(&self.random_rank(f2)),
+        }
+    }
+
+    fn random_rank(&self, file: &File) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.seed.hash(&mut hasher);
+        file.name.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// Reads what a sort order compares for every entry on a background
+/// thread, so sorting a huge directory by size or type doesn't stall the
+/// UI. Dropping the loader stops the thread.
+pub struct SortKeyLoader {
+    pub directory: PathBuf,
+    pub order: SortOrder,
+    receiver: Receiver<Vec<File>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl SortKeyLoader {
+    pub fn spawn(directory: &Path, order: SortOrder, files: Vec<File>) -> Self {
+        let (sender, receiver) = channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let thread_cancelled = Arc::clone(&cancelled);
+        let thread_directory = directory.to_path_buf();
+        std::thread::spawn(move || {
+            for file in &files {
+                if thread_cancelled.load(AtomicOrdering::Relaxed) {
+                    return;
+                }
+                match order.key {
+                    SortKey::Type => {
+                        file.info_type(&thread_directory);
+                    }
+                    _ => {
+                        file.metadata(&thread_directory);
+                    }
+                }
+            }
+            let _ = sender.send(files);
+        });
+        Self {
+            directory: directory.to_path_buf(),
+            order,
+            receiver,
+            cancelled,
+        }
+    }
+
+    /// The entries with their keys read, once they all are.
+    pub fn receive(&self) -> Result<Vec<File>, TryRecvError> {
+        self.receiver.try_recv()
+    }
+}
+
+impl Drop for SortKeyLoader {
+    fn drop(&mut self) {
+        self.cancelled.store(true, AtomicOrdering::Relaxed);
+    }
+}
+
+fn extension(file: &File) -> &[u8] {
+    let name = file.name.as_encoded_bytes();
+    match name.iter().rposition(|byte| *byte == b'.') {
+        Some(0) | None => &[],
+        Some(dot) => &name[dot + 1..],
+    }
+}
+
+/// Compares names so that runs of digits are ordered by their numeric value,
+/// like `ls -v`.
+pub fn natural_cmp(a: &[u8], b: &[u8]) -> Ordering {
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i].is_ascii_digit() && b[j].is_ascii_digit() {
+            let start_a = i;
+            let start_b = j;
+            while i < a.len() && a[i].is_ascii_digit() {
+                i += 1;
+            }
+            while j < b.len() && b[j].is_ascii_digit() {
+                j += 1;
+            }
+            let digits_a = trim_leading_zeros(&a[start_a..i]);
+            let digits_b = trim_leading_zeros(&b[start_b..j]);
+            let ordering = digits_a
+                .len()
+                .cmp(&digits_b.len())
+                .then_with(|| digits_a.cmp(digits_b));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        } else {
+            if a[i] != b[j] {
+                return a[i].cmp(&b[j]);
+            }
+            i += 1;
+            j += 1;
+        }
+    }
+    (a.len() - i).cmp(&(b.len() - j))
+}
+
+fn trim_leading_zeros(digits: &[u8]) -> &[u8] {
+    let zeros = digits.iter().take_while(|digit| **digit == b'0').count();
+    &digits[zeros..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn natural(a: &str, b: &str) -> Ordering {
+        natural_cmp(a.as_bytes(), b.as_bytes())
+    }
+
+    #[test]
+    fn orders_digit_runs_by_value() {
+        assert_eq!(natural("file2", "file10"), Ordering::Less);
+        assert_eq!(natural("file10", "file2"), Ordering::Greater);
+        assert_eq!(natural("v1.10.0", "v1.9.3"), Ordering::Greater);
+        assert_eq!(natural("9", "10"), Ordering::Less);
+    }
+
+    #[test]
+    fn ignores_leading_zeros() {
+        assert_eq!(natural("a01", "a1"), Ordering::Equal);
+        assert_eq!(natural("a007", "a10"), Ordering::Less);
+        assert_eq!(natural("a00", "a0"), Ordering::Equal);
+        assert_eq!(natural("a01b", "a1c"), Ordering::Less);
+    }
+
+    #[test]
+    fn compares_digits_and_other_bytes_as_bytes() {
+        assert_eq!(natural("a1", "ab"), Ordering::Less);
+        assert_eq!(natural("a_", "a1"), Ordering::Greater);
+        assert_eq!(natural("1", "a"), Ordering::Less);
+    }
+
+    #[test]
+    fn puts_a_common_prefix_first() {
+        assert_eq!(natural("file", "file1"), Ordering::Less);
+        assert_eq!(natural("file", "file.txt"), Ordering::Less);
+        assert_eq!(natural("file12", "file12"), Ordering::Equal);
+        assert_eq!(natural("", "a"), Ordering::Less);
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::file::{is_hidden_name, File, FileType};
+use crate::gitignore::IgnoreRules;
+use crate::virtual_listing::{send_in_batches, VirtualEntry};
+use crate::App;
+
+/// The entries of `directory`, relative to `root`, each followed by those
+/// of it if it is expanded, read lazily. Ignored entries are left out if
+/// there are `rules`.
+fn tree_entries(
+    root: PathBuf,
+    directory: PathBuf,
+    show_hidden: bool,
+    mut rules: Option<IgnoreRules>,
+    expanded: HashSet<PathBuf>,
+) -> impl Iterator<Item = File> {
+    let entries = std::fs::read_dir(root.join(&directory)).ok();
+    let mut stack = vec![(directory, entries)];
+    std::iter::from_fn(move || loop {
+        let (directory, entries) = stack.last_mut()?;
+        let Some(entry) = entries.as_mut().and_then(|entries| entries.next()) else {
+            stack.pop();
+            continue;
+        };
+        let Ok(entry) = entry else {
+            continue;
+        };
+        if !show_hidden && is_hidden_name(&entry.file_name()) {
+            continue;
+        }
+        let ftype = entry
+            .file_type()
+            .map(FileType::from)
+            .unwrap_or(FileType::Unknown);
+        let is_dir = ftype == FileType::Directory;
+        if let Some(rules) = &mut rules {
+            if rules.is_ignored(&entry.path(), is_dir) {
+                continue;
+            }
+        }
+        let name = directory.join(entry.file_name());
+        if is_dir && expanded.contains(&root.join(&name)) {
+            let entries = std::fs::read_dir(root.join(&name)).ok();
+            stack.push((name.clone(), entries));
+        }
+        return Some(File::new(ftype, name.into_os_string()));
+    })
+}
+
+/// Sends the entries of `directory` and its expanded subdirectories as a
+/// virtual listing.
+fn send_tree(
+    root: PathBuf,
+    directory: PathBuf,
+    show_hidden: bool,
+    rules: Option<IgnoreRules>,
+    expanded: HashSet<PathBuf>,
+) -> impl FnOnce(Sender<Vec<VirtualEntry>>, Arc<AtomicBool>) + Send + 'static {
+    move |sender, cancelled| {
+        let entries = tree_entries(root, directory, show_hidden, rules, expanded)
+            .take_while(|_| !cancelled.load(Ordering::Relaxed))
+            .map(|file| VirtualEntry {
+                file,
+                preview: None,
+            });
+        send_in_batches(entries, &sender);
+    }
+}
+
+fn depth(name: &OsStr) -> usize {
+    Path::new(name).components().count().saturating_sub(1)
+}
+
+impl App {
+    /// Shows the current directory as a tree whose directories expand in
+    /// place. Stays on while navigating until toggled off.
+    pub fn toggle_tree_view(&mut self) {
+        if self.tree_view {
+            self.tree_view = false;
+            self.close_virtual_listing();
+        } else {
+            self.start_tree();
+        }
+    }
+
+    pub fn start_tree(&mut self) {
+        let produce = self.send_tree(PathBuf::new());
+        self.start_virtual_listing(String::from("tree"), None, produce);
+        self.tree_view = true;
+    }
+
+    fn send_tree(
+        &self,
+        directory: PathBuf,
+    ) -> impl FnOnce(Sender<Vec<VirtualEntry>>, Arc<AtomicBool>) + Send + 'static {
+        send_tree(
+            self.current_directory.clone(),
+            directory,
+            self.show_hidden,
+            self.hide_ignored.then(IgnoreRules::default),
+            self.expanded.clone(),
+        )
+    }
+
+    /// Whether an entry arriving in the background still goes into the tree.
+    /// Children of a directory collapsed while they were read are left out,
+    /// and so is what an earlier expansion of it already listed.
+    pub fn belongs_in_tree(&self, file: &File) -> bool {
+        let expanded = Path::new(&file.name)
+            .parent()
+            .is_none_or(|parent| self.tree_shows_contents(parent));
+        let order = self.sort_order(&self.current_directory);
+        let is_listed = |files: &[File]| {
+            files
+                .binary_search_by(|other| order.compare(other, file, &self.current_directory))
+                .is_ok()
+        };
+        expanded && !is_listed(&self.current_directory_contents) && !is_listed(&self.filtered_out)
+    }
+
+    /// Whether the tree lists what is in `directory`, relative to the current
+    /// directory, because it and every directory above it are expanded.
+    pub fn tree_shows_contents(&self, directory: &Path) -> bool {
+        directory
+            .ancestors()
+            .filter(|ancestor| !ancestor.as_os_str().is_empty())
+            .all(|ancestor| self.is_expanded(ancestor.as_os_str()))
+    }
+
+    fn is_expanded(&self, name: &OsStr) -> bool {
+        self.expanded.contains(&self.current_directory.join(name))
+    }
+
+    pub fn can_expand_selected(&self) -> bool {
+        self.current_directory_contents
+            .get(self.current_selection)
+            .is_some_and(|file| file.ftype == FileType::Directory && !self.is_expanded(&file.name))
+    }
+
+    /// Lists the children of the selected directory below it as they are
+    /// read in the background.
+    pub fn expand_selected(&mut self) {
+        let Some(name) = self.selected_name() else {
+            return;
+        };
+        self.expanded.insert(self.current_directory.join(&name));
+        let produce = self.send_tree(PathBuf::from(name));
+        self.extend_virtual_listing(produce);
+    }
+
+    fn collapse(&mut self, name: &OsStr) {
+        self.expanded.remove(&self.current_directory.join(name));
+        let is_inside = |file: &File| file.name != name && Path::new(&file.name).starts_with(name);
+        self.current_directory_contents
+            .retain(|file| !is_inside(file));
+        self.filtered_out.retain(|file| !is_inside(file));
+        self.select_by_name(name);
+    }
+
+    /// Collapses the selected directory, or moves up to the parent of the
+    /// selected entry, leaving the directory at the top level.
+    pub fn tree_navigate_up(&mut self) -> Result<()> {
+        let Some(name) = self.selected_name() else {
+            return self.navigate_up();
+        };
+        if self.is_expanded(&name) {
+            self.collapse(&name);
+            return Ok(());
+        }
+        match Path::new(&name).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => {
+                self.select_by_name(parent.as_os_str());
+                self.update_selected_item();
+                Ok(())
+            }
+            _ => self.navigate_up(),
+        }
+    }
+
+    /// Indentation guides for the entry at `index`, `│`s for the levels
+    /// that continue below it.
+    pub fn tree_guides(&self, index: usize) -> String {
+        let files = &self.current_directory_contents;
+        let Some(file) = files.get(index) else {
+            return String::new();
+        };
+        let has_next_sibling = |level: usize| {
+            files[index + 1..]
+                .iter()
+                .map(|file| depth(&file.name))
+                .find(|&depth| depth <= level)
+                == Some(level)
+        };
+        let depth = depth(&file.name);
+        let mut guides = String::new();
+        for level in 1..depth {
+            guides += if has_next_sibling(level) {
+                "│  "
+            } else {
+                "   "
+            };
+        }
+        if depth > 0 {
+            guides += if has_next_sibling(depth) {
+                "├─ "
+            } else {
+                "└─ "
+            };
+        }
+        guides += match file.ftype {
+            FileType::Directory if self.is_expanded(&file.name) => "▾ ",
+            FileType::Directory => "▸ ",
+            _ => "  ",
+        };
+        guides
+    }
+}
@@ -1,28 +1,34 @@
 use crate::external::{get_media_length, run_external_command};
-use crate::file::{directory_contents, FileType};
-use crate::info::Info;
-use crate::{App, ApplicationEvent};
+use crate::file::File;
+use crate::file::FileType;
+use crate::gitignore::IgnoreRules;
+use crate::metadata::free_space;
+use crate::preview::{placeholder, Neighbor};
+use crate::sort::{SortKeyLoader, SortOrder};
+use crate::{App, ApplicationEvent, Prompt};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::mpsc::{channel, TryRecvError};
 
 impl App {
     pub fn update(&mut self) {
+        self.handle_signals();
         self.update_window_size();
-        if self.directory_changed {
-            self.current_directory_contents =
-                directory_contents(&self.current_directory, self.show_hidden);
-            self.parent_directory_contents = directory_contents(
-                &self.parent_directory().unwrap_or("\\".into()),
-                self.show_hidden,
-            );
-            self.directory_changed = false;
-
-            self.current_selection = 0;
-            self.update_selected_item();
-        }
+        self.receive_listings();
+        self.receive_sort_keys();
+        self.handle_filesystem_events();
+        self.receive_previews();
+        self.continue_preview_search();
+        self.receive_finder_results();
+        self.receive_virtual_listing();
+        self.receive_git_status();
+        self.receive_git_job();
+        self.receive_trash_job();
 
         let mut events = std::mem::take(&mut self.new_events);
         for event in events.drain(..) {
@@ -31,6 +37,23 @@ impl App {
                     self.should_run = false;
                     Ok(())
                 }
+                ApplicationEvent::NavigateUp if self.tree_view => self.tree_navigate_up(),
+                ApplicationEvent::NavigateDown if self.tree_view && self.can_expand_selected() => {
+                    self.expand_selected();
+                    Ok(())
+                }
+                ApplicationEvent::NavigateUp
+                    if self.virtual_listing.is_some() && !self.flatten && !self.tree_view =>
+                {
+                    self.close_virtual_listing();
+                    Ok(())
+                }
+                ApplicationEvent::NavigateDown
+                    if self.virtual_listing.is_some() && !self.flatten && !self.tree_view =>
+                {
+                    self.jump_to_virtual_entry();
+                    Ok(())
+                }
                 ApplicationEvent::NavigateUp => self.navigate_up(),
                 ApplicationEvent::NavigateDown => match self.navigate_down() {
                     Ok(_) => Ok(()),
@@ -72,62 +95,286 @@ impl App {
                         }
                     }
                 },
-                ApplicationEvent::SelectNext => self.change_selection(1),
-                ApplicationEvent::SelectPrevious => self.change_selection(-1),
+                ApplicationEvent::SelectNext => match &mut self.finder {
+                    Some(finder) => {
+                        finder.change_selection(1);
+                        Ok(())
+                    }
+                    None => self.change_selection(1),
+                },
+                ApplicationEvent::SelectPrevious => match &mut self.finder {
+                    Some(finder) => {
+                        finder.change_selection(-1);
+                        Ok(())
+                    }
+                    None => self.change_selection(-1),
+                },
                 ApplicationEvent::ToggleShowHidden => {
+                    self.stash_listings();
                     self.show_hidden = !self.show_hidden;
                     self.directory_changed = true;
                     Ok(())
                 }
-                ApplicationEvent::OpenImage => {
-                    let command = "pfiew";
-                    let args = self.selected_item.clone().unwrap();
-                    let args = args.to_str().unwrap();
-                    self.run_command(command, &[format!("--input={}", args).as_str()])
+                ApplicationEvent::Stage => self.stage(),
+                ApplicationEvent::Unstage => self.unstage(),
+                ApplicationEvent::Discard => self.start_discard(),
+                ApplicationEvent::StartCommit => {
+                    self.start_commit();
+                    Ok(())
                 }
-                ApplicationEvent::OpenImageFolder => {
-                    let command = "pfiew";
-                    let path = self.selected_item.clone().unwrap();
-                    let folder = &path.parent().unwrap();
-                    let args = folder.to_str().unwrap();
-                    self.run_command(command, &[format!("--input={}", args).as_str()])
+                ApplicationEvent::ScrollPreview(change_by) => {
+                    self.scroll_preview(change_by);
+                    Ok(())
                 }
-                ApplicationEvent::OpenText => {
-                    let command = "micro";
-                    let args = self.selected_item.clone().unwrap();
-                    let args = args.to_str().unwrap();
-                    self.run_command(command, &[args])
+                ApplicationEvent::ScrollPreviewPages(pages) => {
+                    self.scroll_preview_pages(pages);
+                    Ok(())
                 }
-                ApplicationEvent::OpenExecutable => {
-                    let command = self.selected_item.clone().unwrap();
-                    let command = command.to_str().unwrap();
-                    self.run_command(command, &[])
+                ApplicationEvent::ScrollPreviewSideways(steps) => {
+                    self.scroll_preview_sideways(steps);
+                    Ok(())
+                }
+                ApplicationEvent::ToggleSoftWrap => {
+                    self.toggle_soft_wrap();
+                    Ok(())
                 }
+                ApplicationEvent::CycleGitView => {
+                    self.cycle_git_view();
+                    Ok(())
+                }
+                ApplicationEvent::ToggleHideIgnored => {
+                    self.stash_listings();
+                    self.hide_ignored = !self.hide_ignored;
+                    self.ignore_rules = IgnoreRules::default();
+                    self.directory_changed = true;
+                    Ok(())
+                }
+                ApplicationEvent::OpenImage => self.selected_path().and_then(|path| {
+                    let command = "pfiew";
+                    let mut arg = OsString::from("--input=");
+                    arg.push(path);
+                    self.run_command(command, &[arg])
+                }),
+                ApplicationEvent::OpenImageFolder => self.selected_path().and_then(|path| {
+                    let command = "pfiew";
+                    let folder = path
+                        .parent()
+                        .ok_or(anyhow!("Selected item has no parent directory"))?;
+                    let mut arg = OsString::from("--input=");
+                    arg.push(folder);
+                    self.run_command(command, &[arg])
+                }),
+                ApplicationEvent::OpenText => self.selected_path().and_then(|path| {
+                    let command = "micro";
+                    self.run_command(command, &[path])
+                }),
+                ApplicationEvent::OpenExecutable => self
+                    .selected_path()
+                    .and_then(|path| self.run_command(path, &[] as &[&OsStr])),
                 ApplicationEvent::PlayMedia => {
-                    let path = self.selected_item.clone().unwrap();
-                    self.play_media(path.to_str().unwrap())
+                    self.selected_path().and_then(|path| self.play_media(&path))
                 }
                 ApplicationEvent::DebugEvent => {
                     self.msg("q!!");
                     Ok(())
                 }
-                ApplicationEvent::ReadPdf => {
+                ApplicationEvent::ReadPdf => self.selected_path().and_then(|path| {
                     let command = "zathura";
-                    let args = self.selected_item.clone().unwrap();
-                    let args = args.to_str().unwrap();
-                    self.run_command(command, &["--fork", args])
-                }
-                ApplicationEvent::RunShellScript => {
+                    self.run_command(command, &[OsStr::new("--fork"), path.as_os_str()])
+                }),
+                ApplicationEvent::RunShellScript => self.selected_path().and_then(|path| {
                     let command = "bash";
-                    let path = self.selected_item.clone().unwrap();
-                    let path = path.to_str().unwrap();
-                    self.run_command(command, &["-c", path])
+                    self.run_command(command, &[OsStr::new("-c"), path.as_os_str()])
+                }),
+                ApplicationEvent::Suspend => self.suspend(),
+                ApplicationEvent::ToggleMark => self.toggle_mark(),
+                ApplicationEvent::CycleSortKey => {
+                    let order = self.requested_sort_order();
+                    self.set_sort_order(order.with_key(order.key.next()));
+                    Ok(())
+                }
+                ApplicationEvent::ToggleSortReverse => {
+                    let order = self.requested_sort_order();
+                    self.set_sort_order(order.reversed());
+                    Ok(())
+                }
+                ApplicationEvent::ToggleDirectoriesFirst => {
+                    let order = self.requested_sort_order();
+                    self.set_sort_order(order.toggle_directories_first());
+                    Ok(())
+                }
+                ApplicationEvent::StartFilter => {
+                    self.start_filter();
+                    Ok(())
+                }
+                ApplicationEvent::ClearFilter => {
+                    self.clear_filter();
+                    Ok(())
+                }
+                ApplicationEvent::CycleFilterMode => {
+                    self.edit_filter(|filter| filter.mode = filter.mode.next());
+                    Ok(())
+                }
+                ApplicationEvent::ToggleStickyFilters => {
+                    self.sticky_filters = !self.sticky_filters;
+                    self.msg(match self.sticky_filters {
+                        true => "Filters are kept per directory",
+                        false => "Filters are cleared on navigation",
+                    });
+                    Ok(())
+                }
+                ApplicationEvent::StartSearch => {
+                    self.start_search();
+                    Ok(())
+                }
+                ApplicationEvent::SearchNext => self.search_next(true),
+                ApplicationEvent::SearchPrevious => self.search_next(false),
+                ApplicationEvent::SearchPreview(forward) => self.search_preview(forward),
+                ApplicationEvent::StartFinder => {
+                    self.start_finder();
+                    Ok(())
+                }
+                ApplicationEvent::ToggleGitignore => {
+                    self.respect_gitignore = !self.respect_gitignore;
+                    self.restart_finder();
+                    Ok(())
+                }
+                ApplicationEvent::StartGrep => {
+                    self.start_grep();
+                    Ok(())
+                }
+                ApplicationEvent::StartFind => {
+                    self.start_find();
+                    Ok(())
+                }
+                ApplicationEvent::Trash => self.start_trash(),
+                ApplicationEvent::ToggleFlatten => {
+                    self.toggle_flatten();
+                    Ok(())
+                }
+                ApplicationEvent::ToggleFlattenFilesOnly => {
+                    self.toggle_flatten_files_only();
+                    Ok(())
+                }
+                ApplicationEvent::ToggleTreeView => {
+                    self.toggle_tree_view();
+                    Ok(())
+                }
+                ApplicationEvent::ChangeFlattenDepth(change_by) => {
+                    self.change_flatten_depth(change_by);
+                    Ok(())
+                }
+                ApplicationEvent::PromptPush(c) if self.prompt == Some(Prompt::ConfirmDiscard) => {
+                    self.answer_discard(c)
+                }
+                ApplicationEvent::PromptPush(c) if self.prompt == Some(Prompt::ConfirmTrash) => {
+                    self.answer_trash(c)
+                }
+                ApplicationEvent::PromptPush(c) => {
+                    match self.prompt {
+                        Some(Prompt::Commit) => self.commit_message.push(c),
+                        Some(Prompt::Grep) => self.grep_pattern.push(c),
+                        Some(Prompt::Find) => self.find_query.push(c),
+                        Some(Prompt::Finder) => self.edit_finder_query(|query| query.push(c)),
+                        Some(Prompt::Filter) => self.edit_filter(|filter| filter.pattern.push(c)),
+                        Some(Prompt::Search) => self.edit_search(|pattern| pattern.push(c)),
+                        Some(Prompt::ConfirmDiscard | Prompt::ConfirmTrash) | None => (),
+                    }
+                    Ok(())
+                }
+                ApplicationEvent::PromptPop => {
+                    match self.prompt {
+                        Some(Prompt::Filter) => self.edit_filter(|filter| {
+                            filter.pattern.pop();
+                        }),
+                        Some(Prompt::Search) => self.edit_search(|pattern| {
+                            pattern.pop();
+                        }),
+                        Some(Prompt::Finder) => self.edit_finder_query(|query| {
+                            query.pop();
+                        }),
+                        Some(Prompt::Grep) => {
+                            self.grep_pattern.pop();
+                        }
+                        Some(Prompt::Commit) => {
+                            self.commit_message.pop();
+                        }
+                        Some(Prompt::Find) => {
+                            self.find_query.pop();
+                        }
+                        Some(Prompt::ConfirmDiscard | Prompt::ConfirmTrash) | None => (),
+                    }
+                    Ok(())
+                }
+                ApplicationEvent::AcceptPrompt => match self.prompt {
+                    Some(Prompt::Grep) => self.run_grep(),
+                    Some(Prompt::Find) => self.run_find(),
+                    Some(Prompt::Commit) => self.run_commit(),
+                    Some(Prompt::Filter) => {
+                        self.accept_filter();
+                        Ok(())
+                    }
+                    Some(Prompt::Search) => {
+                        self.accept_search();
+                        Ok(())
+                    }
+                    Some(Prompt::Finder) => {
+                        self.accept_finder();
+                        Ok(())
+                    }
+                    Some(Prompt::ConfirmDiscard | Prompt::ConfirmTrash) => {
+                        self.cancel_confirmation();
+                        Ok(())
+                    }
+                    None => Ok(()),
+                },
+                ApplicationEvent::CancelPrompt => {
+                    match self.prompt {
+                        Some(Prompt::Filter) => self.clear_filter(),
+                        Some(Prompt::Search) => self.cancel_search(),
+                        Some(Prompt::Finder) => self.close_finder(),
+                        Some(Prompt::Grep) | Some(Prompt::Find) | Some(Prompt::Commit) => {
+                            self.prompt = None
+                        }
+                        Some(Prompt::ConfirmDiscard | Prompt::ConfirmTrash) => {
+                            self.cancel_confirmation()
+                        }
+                        None => (),
+                    }
+                    Ok(())
+                }
+                ApplicationEvent::ToggleLongListing => {
+                    self.long_listing = !self.long_listing;
+                    Ok(())
+                }
+                ApplicationEvent::CycleLongListingFields => {
+                    self.cycle_long_listing_fields();
+                    Ok(())
                 }
             };
             if let Err(err) = result {
                 self.msg(format!("Error: {}", err));
             }
         }
+        // Handled after the events so a new directory is listed in the same
+        // frame it is entered.
+        if self.directory_changed {
+            self.load_listings(true);
+            self.directory_changed = false;
+            if self.flatten {
+                self.start_flatten();
+            } else if self.tree_view {
+                self.start_tree();
+            }
+            self.update_free_space();
+            self.refresh_git_status();
+
+            self.current_selection = 0;
+            self.select_pending();
+            self.update_selected_item();
+        }
+        self.count_preview_matches();
+
         let mut msg = None;
         let mut keep_children = Vec::new();
         for mut child in &mut self.children.drain(..) {
@@ -151,17 +398,27 @@ impl App {
         if let Ok(new_size) = crossterm::terminal::window_size() {
             self.width = 80.max((new_size.columns - 5).into());
             self.height = 15.max((new_size.rows - 2).into());
+            self.screen
+                .resize(new_size.columns.into(), new_size.rows.into());
+            if self.previewer.set_size(self.preview_size()) {
+                self.update_selected_item();
+            }
         }
     }
 
-    fn parent_directory(&self) -> std::option::Option<PathBuf> {
+    /// Reads both listings from disk again, bypassing the cache.
+    pub fn reload_listings(&mut self) {
+        self.load_listings(false);
+    }
+
+    pub fn parent_directory(&self) -> std::option::Option<PathBuf> {
         self.current_directory
             .ancestors()
             .nth(1)
             .map(|path| path.to_path_buf())
     }
 
-    fn navigate_up(&mut self) -> Result<()> {
+    pub fn navigate_up(&mut self) -> Result<()> {
         let parent_directory = self
             .parent_directory()
             .ok_or(anyhow!("No parent directory available"))?;
@@ -183,25 +440,80 @@ impl App {
         }
     }
 
-    fn change_directory(&mut self, to: PathBuf) {
+    pub fn change_directory(&mut self, to: PathBuf) {
+        self.stash_listings();
+        self.virtual_listing = None;
+        self.sort_key_loader = None;
+        self.prompt = None;
+        self.switch_filter(&to);
         self.current_directory = to;
         self.directory_changed = true;
     }
 
-    fn update_selected_item(&mut self) {
+    pub fn update_selected_item(&mut self) {
         match self.current_directory_contents.get(self.current_selection) {
             Some(item) => {
-                self.selected_item = Some(self.current_directory.join(item.name.clone()));
-                let path = self.selected_item.as_ref().unwrap();
+                let path = self.current_directory.join(item.name.clone());
+                let replacement_preview = self
+                    .virtual_listing
+                    .as_ref()
+                    .and_then(|listing| listing.preview(&item.name));
                 match item.ftype {
-                    FileType::File => self.selection_info = Info::new(path).ok(),
-                    FileType::Directory => self.selection_info = Some(Info::directory(path)),
-                    FileType::Link => self.selection_info = Some(Info::link(path)),
+                    _ if replacement_preview.is_some() => {
+                        self.selection_info = replacement_preview;
+                        self.selected_item = Some(path);
+                    }
                     FileType::Unknown => self.selected_item = None,
+                    ftype => {
+                        let same_file = self.selected_item.as_ref() == Some(&path);
+                        if !same_file {
+                            self.preview_scroll = 0;
+                            self.preview_column = 0;
+                            self.preview_search_lines = 0;
+                            self.pending_preview_search = None;
+                            self.previewer.set_size(self.preview_size());
+                        }
+                        let neighbors = self.selection_neighbors();
+                        let git = self.git_preview(&path, ftype);
+                        match self.previewer.request(&path, ftype, git, &neighbors) {
+                            Some(info) => self.selection_info = Some(info),
+                            // Keeps showing the file until its new preview is built.
+                            None if same_file && self.selection_info.is_some() => (),
+                            None => self.selection_info = Some(placeholder(&path, ftype)),
+                        }
+                        self.selected_item = Some(path);
+                    }
                 }
             }
-            _ => self.selected_item = None,
+            _ => {
+                self.selected_item = None;
+                self.selection_info = None;
+            }
         };
+        self.update_watches();
+    }
+
+    fn selection_neighbors(&self) -> Vec<Neighbor> {
+        [
+            self.current_selection.checked_sub(1),
+            self.current_selection.checked_add(1),
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(|index| self.current_directory_contents.get(index))
+        .filter(|file| file.ftype != FileType::Unknown)
+        .map(|file| {
+            let path = self.current_directory.join(&file.name);
+            let git = self.git_preview(&path, file.ftype);
+            (path, file.ftype, git)
+        })
+        .collect()
+    }
+
+    fn receive_previews(&mut self) {
+        if let Some(info) = self.previewer.receive() {
+            self.selection_info = Some(info);
+        }
     }
 
     fn change_selection(&mut self, change_by: i32) -> Result<()> {
@@ -224,19 +536,254 @@ impl App {
         }
 
         self.current_selection = next_selection as usize;
+        self.clamp_selection();
         self.update_selected_item();
         Ok(())
     }
 
-    fn run_command(&mut self, command: &str, args: &[&str]) -> Result<()> {
-        self.msg(format!("Running {} with {:?}", command, args));
+    /// Keeps the selection inside the listing, which can shrink when it is
+    /// filtered or entries are removed.
+    pub fn clamp_selection(&mut self) {
+        self.current_selection = self
+            .current_selection
+            .min(self.current_directory_contents.len().saturating_sub(1));
+    }
+
+    pub fn update_free_space(&mut self) {
+        self.free_space = free_space(&self.current_directory);
+    }
+
+    pub fn sort_order(&self, directory: &Path) -> SortOrder {
+        let order = self
+            .sort_orders
+            .get(directory)
+            .copied()
+            .unwrap_or(self.default_sort_order);
+        order.in_tree(self.tree_view && directory == self.current_directory)
+    }
+
+    /// The order of the current directory, or the one it is about to get.
+    fn requested_sort_order(&self) -> SortOrder {
+        match &self.sort_key_loader {
+            Some(loader) => loader.order,
+            None => self.sort_order(&self.current_directory),
+        }
+    }
+
+    /// Changes the sort of the current directory, keeping the cursor on the
+    /// same file. Keys that need the disk are read in the background first.
+    fn set_sort_order(&mut self, order: SortOrder) {
+        self.msg(format!("Sorting by {}", order.describe()));
+        self.sort_key_loader = None;
+        if self.current_loader.is_some() {
+            // Batches still in flight are sorted the old way, start over.
+            self.sort_orders
+                .insert(self.current_directory.clone(), order.in_tree(false));
+            self.reload_listings();
+            return;
+        }
+        if order.reads_disk() {
+            let files = self
+                .current_directory_contents
+                .iter()
+                .chain(&self.filtered_out)
+                .map(|file| File::new(file.ftype, file.name.clone()))
+                .collect();
+            self.sort_key_loader =
+                Some(SortKeyLoader::spawn(&self.current_directory, order, files));
+        } else {
+            self.apply_sort_order(order);
+        }
+    }
+
+    fn receive_sort_keys(&mut self) {
+        let Some(loader) = &self.sort_key_loader else {
+            return;
+        };
+        let files = match loader.receive() {
+            Ok(files) => files,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => Vec::new(),
+        };
+        let order = loader.order;
+        let directory = loader.directory.clone();
+        self.sort_key_loader = None;
+        if directory != self.current_directory || files.is_empty() {
+            return;
+        }
+        let mut loaded: HashMap<OsString, File> = files
+            .into_iter()
+            .map(|file| (file.name.clone(), file))
+            .collect();
+        for file in self
+            .current_directory_contents
+            .iter()
+            .chain(&self.filtered_out)
+        {
+            if let Some(copy) = loaded.remove(&file.name) {
+                file.fill_from(copy);
+            }
+        }
+        self.apply_sort_order(order);
+    }
+
+    fn apply_sort_order(&mut self, order: SortOrder) {
+        self.sort_orders
+            .insert(self.current_directory.clone(), order.in_tree(false));
+        let selected = self.selected_name();
+        let directory = self.current_directory.clone();
+        self.current_directory_contents
+            .sort_by(|f1, f2| order.compare(f1, f2, &directory));
+        self.filtered_out
+            .sort_by(|f1, f2| order.compare(f1, f2, &directory));
+        if let Some(selected) = selected {
+            self.select_by_name(&selected);
+        }
+    }
+
+    pub fn selected_name(&self) -> Option<OsString> {
+        self.current_directory_contents
+            .get(self.current_selection)
+            .map(|file| file.name.clone())
+    }
+
+    /// Puts the cursor on the pending entry if it was listed by now. Returns
+    /// whether the cursor moved.
+    pub fn select_pending(&mut self) -> bool {
+        let Some(name) = self.pending_selection.clone() else {
+            return false;
+        };
+        let found = self.select_by_name(&name);
+        if found || !self.is_loading_listing() {
+            self.pending_selection = None;
+        }
+        found
+    }
+
+    /// Moves the cursor to `name` if it is listed, returns whether it was.
+    pub fn select_by_name(&mut self, name: &OsStr) -> bool {
+        match self
+            .current_directory_contents
+            .iter()
+            .position(|file| file.name == name)
+        {
+            Some(index) => {
+                self.current_selection = index;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn toggle_mark(&mut self) -> Result<()> {
+        let path = self.selected_path()?;
+        if !self.marked.remove(&path) {
+            self.marked.insert(path);
+        }
+        self.change_selection(1)
+    }
+
+    /// Asks before trashing the marked files, or the selected one if none
+    /// are marked.
+    fn start_trash(&mut self) -> Result<()> {
+        self.confirm(Prompt::ConfirmTrash)
+    }
+
+    /// Opens a y/n prompt for an action on the marked or selected files.
+    pub fn confirm(&mut self, prompt: Prompt) -> Result<()> {
+        self.confirm_targets = self.targets()?;
+        let shown: HashSet<PathBuf> = self
+            .current_directory_contents
+            .iter()
+            .map(|file| self.current_directory.join(&file.name))
+            .collect();
+        self.confirm_not_shown = self
+            .confirm_targets
+            .iter()
+            .filter(|path| !shown.contains(*path))
+            .count();
+        self.prompt = Some(prompt);
+        Ok(())
+    }
+
+    /// Anything but y cancels.
+    fn answer_trash(&mut self, answer: char) -> Result<()> {
+        let paths = std::mem::take(&mut self.confirm_targets);
+        self.prompt = None;
+        match answer {
+            'y' | 'Y' => self.trash(paths),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn cancel_confirmation(&mut self) {
+        self.confirm_targets.clear();
+        self.prompt = None;
+    }
+
+    /// Runs trash-put in the background, it can take a while for many files
+    /// or another filesystem.
+    fn trash(&mut self, paths: Vec<PathBuf>) -> Result<()> {
+        if self.trash_job.is_some() {
+            bail!("Still trashing the previous files");
+        }
+        let (sender, receiver) = channel();
+        std::thread::spawn(move || {
+            let message = match run_external_command("trash-put", &paths) {
+                Ok(_) if paths.len() == 1 => String::from("Trashed 1 file"),
+                Ok(_) => format!("Trashed {} files", paths.len()),
+                Err(err) => format!("Unable to trash: {err}"),
+            };
+            let _ = sender.send(message);
+        });
+        self.trash_job = Some(receiver);
+        Ok(())
+    }
+
+    pub fn receive_trash_job(&mut self) {
+        let Some(receiver) = &self.trash_job else {
+            return;
+        };
+        match receiver.try_recv() {
+            Ok(message) => self.msg(message),
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => (),
+        }
+        self.trash_job = None;
+        self.marked.retain(|path| path.symlink_metadata().is_ok());
+        // Virtual listings aren't watched, so drop what is gone by hand.
+        if self.virtual_listing.is_some() {
+            let directory = self.current_directory.clone();
+            let exists =
+                |file: &crate::file::File| directory.join(&file.name).symlink_metadata().is_ok();
+            self.current_directory_contents.retain(exists);
+            self.filtered_out.retain(exists);
+            self.clamp_selection();
+            self.update_selected_item();
+        }
+    }
+
+    fn selected_path(&self) -> Result<PathBuf> {
+        self.selected_item
+            .clone()
+            .ok_or(anyhow!("No item selected!"))
+    }
+
+    fn run_command(
+        &mut self,
+        command: impl AsRef<OsStr>,
+        args: &[impl AsRef<OsStr>],
+    ) -> Result<()> {
+        let command = command.as_ref();
+        let debug_args: Vec<&OsStr> = args.iter().map(|arg| arg.as_ref()).collect();
+        self.msg(format!("Running {:?} with {:?}", command, debug_args));
         match run_external_command(command, args) {
             Ok(_output) => Ok(()),
             Err(err) => Err(err),
         }
     }
 
-    fn play_media(&mut self, path: &str) -> Result<()> {
+    fn play_media(&mut self, path: &Path) -> Result<()> {
         let command = "mpv";
         let is_long = get_media_length(path)? > 1.5;
         if is_long {
@@ -246,12 +793,13 @@ impl App {
             let _ = Command::new(command).args(args).spawn().unwrap().wait();
             self.setup_terminal()?;
         } else {
+            let path = path.as_os_str();
             let args = &[
                 path,
-                "--really-quiet",
-                "--no-input-default-bindings",
-                "--no-config",
-                "--volume=50",
+                OsStr::new("--really-quiet"),
+                OsStr::new("--no-input-default-bindings"),
+                OsStr::new("--no-config"),
+                OsStr::new("--volume=50"),
             ];
             let child = Command::new(command)
                 .args(args)
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+use crate::file::File;
+use crate::info::Info;
+use crate::App;
+
+const BATCH_SIZE: usize = 256;
+const BATCH_INTERVAL: Duration = Duration::from_millis(50);
+
+/// An entry of a virtual listing. Its name is a path relative to the
+/// current directory, so everything that joins names onto the directory
+/// keeps working.
+pub struct VirtualEntry {
+    pub file: File,
+    /// Shown instead of the regular preview.
+    pub preview: Option<Info>,
+}
+
+/// Results of a search shown in place of the current directory's listing.
+/// Entries are produced on background threads, dropping the listing stops
+/// them.
+pub struct VirtualListing {
+    pub title: String,
+    /// Matches highlighted in the replacement previews.
+    pub highlight: Option<Regex>,
+    previews: HashMap<OsString, Arc<Info>>,
+    /// One for each producer still running.
+    receivers: Vec<Receiver<Vec<VirtualEntry>>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl VirtualListing {
+    pub fn is_loading(&self) -> bool {
+        !self.receivers.is_empty()
+    }
+
+    fn spawn(
+        &mut self,
+        produce: impl FnOnce(Sender<Vec<VirtualEntry>>, Arc<AtomicBool>) + Send + 'static,
+    ) {
+        let (sender, receiver) = channel();
+        let thread_cancelled = Arc::clone(&self.cancelled);
+        std::thread::spawn(move || produce(sender, thread_cancelled));
+        self.receivers.push(receiver);
+    }
+
+    pub fn preview(&self, name: &OsStr) -> Option<Arc<Info>> {
+        self.previews.get(name).cloned()
+    }
+}
+
+impl Drop for VirtualListing {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Sends `entries` on in batches, so the listing fills up steadily
+/// without a message per entry. Stops when the listing is gone.
+pub fn send_in_batches(
+    entries: impl Iterator<Item = VirtualEntry>,
+    sender: &Sender<Vec<VirtualEntry>>,
+) {
+    let mut batch = Vec::new();
+    let mut last_sent = Instant::now();
+    for entry in entries {
+        batch.push(entry);
+        if batch.len() >= BATCH_SIZE || last_sent.elapsed() >= BATCH_INTERVAL {
+            if sender.send(std::mem::take(&mut batch)).is_err() {
+                return;
+            }
+            last_sent = Instant::now();
+        }
+    }
+    let _ = sender.send(batch);
+}
+
+impl App {
+    /// Replaces the current listing with the entries `produce` sends. It runs
+    /// on its own thread and should stop once the flag is set.
+    pub fn start_virtual_listing(
+        &mut self,
+        title: String,
+        highlight: Option<Regex>,
+        produce: impl FnOnce(Sender<Vec<VirtualEntry>>, Arc<AtomicBool>) + Send + 'static,
+    ) {
+        if self.virtual_listing.take().is_none() {
+            self.stash_current_listing();
+        }
+        self.flatten = false;
+        self.tree_view = false;
+        self.current_loader = None;
+        self.sort_key_loader = None;
+        self.current_directory_contents.clear();
+        self.filtered_out.clear();
+        self.current_selection = 0;
+
+        let mut listing = VirtualListing {
+            title,
+            highlight,
+            previews: HashMap::new(),
+            receivers: Vec::new(),
+            cancelled: Arc::new(AtomicBool::new(false)),
+        };
+        listing.spawn(produce);
+        self.virtual_listing = Some(listing);
+        self.update_selected_item();
+    }
+
+    /// Merges what another producer sends into the virtual listing.
+    pub fn extend_virtual_listing(
+        &mut self,
+        produce: impl FnOnce(Sender<Vec<VirtualEntry>>, Arc<AtomicBool>) + Send + 'static,
+    ) {
+        if let Some(listing) = &mut self.virtual_listing {
+            listing.spawn(produce);
+        }
+    }
+
+    pub fn receive_virtual_listing(&mut self) {
+        let Some(listing) = &mut self.virtual_listing else {
+            return;
+        };
+        if listing.receivers.is_empty() {
+            return;
+        }
+        let mut batches = Vec::new();
+        listing.receivers.retain(|receiver| loop {
+            match receiver.try_recv() {
+                Ok(batch) => batches.push(batch),
+                Err(TryRecvError::Empty) => break true,
+                Err(TryRecvError::Disconnected) => break false,
+            }
+        });
+        if batches.is_empty() {
+            if listing.receivers.is_empty() {
+                self.pending_selection = None;
+            }
+            return;
+        }
+
+        let selected = self.selected_name();
+        let order = self.sort_order(&self.current_directory);
+        // Everything that arrived goes in with one merge, a merge for every
+        // batch goes over the whole listing again.
+        let mut files = Vec::with_capacity(batches.iter().map(Vec::len).sum());
+        for entry in batches.into_iter().flatten() {
+            if self.tree_view && !self.belongs_in_tree(&entry.file) {
+                continue;
+            }
+            if let (Some(preview), Some(listing)) = (entry.preview, &mut self.virtual_listing) {
+                listing
+                    .previews
+                    .insert(entry.file.name.clone(), Arc::new(preview));
+            }
+            files.push(entry.file);
+        }
+        files.sort_by(|f1, f2| order.compare(f1, f2, &self.current_directory));
+        // Expanding a directory again while it is read lists it twice.
+        files.dedup_by(|f1, f2| f1.name == f2.name);
+        self.merge_into_current(files);
+        if self.select_pending() {
+            self.update_selected_item();
+            return;
+        }
+        match selected {
+            Some(selected) => {
+                self.select_by_name(&selected);
+            }
+            None => self.update_selected_item(),
+        }
+    }
+
+    /// Goes back to the real listing of the current directory.
+    pub fn close_virtual_listing(&mut self) {
+        if self.virtual_listing.take().is_some() {
+            self.sort_key_loader = None;
+            self.current_directory_contents.clear();
+            self.filtered_out.clear();
+            self.directory_changed = true;
+        }
+    }
+
+    /// Leaves the virtual listing for the directory holding the selected
+    /// entry, with the cursor on it.
+    pub fn jump_to_virtual_entry(&mut self) {
+        let Some(path) = self.selected_item.clone() else {
+            return;
+        };
+        self.close_virtual_listing();
+        if let (Some(directory), Some(name)) = (path.parent(), path.file_name()) {
+            self.pending_selection = Some(OsString::from(name));
+            self.change_directory(directory.to_path_buf());
+        }
+    }
+}
@@ -0,0 +1,404 @@
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+
+use crate::file::{insert_file, remove_path, File, FileType};
+use crate::gitignore::{is_ignore_file, IgnoreRules};
+use crate::sort::SortOrder;
+use crate::App;
+
+// Events are applied once the directory has been quiet for DEBOUNCE, or at
+// the latest MAX_DELAY after the first one, so a long burst still shows up.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+const MAX_DELAY: Duration = Duration::from_millis(1000);
+/// Directories of a flattened listing watched at most, each one takes up a
+/// kernel watch.
+const MAX_FLATTEN_WATCHES: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub enum FilesystemEvent {
+    Added {
+        directory: PathBuf,
+        name: OsString,
+    },
+    Removed {
+        directory: PathBuf,
+        name: OsString,
+    },
+    Renamed {
+        directory: PathBuf,
+        from: OsString,
+        to: OsString,
+    },
+    Modified {
+        directory: PathBuf,
+        name: OsString,
+    },
+    /// The kernel queue overflowed and events were lost.
+    Overflow,
+}
+
+pub struct Watcher {
+    inotify: Inotify,
+    watches: Vec<(WatchDescriptor, PathBuf)>,
+    buffer: Vec<u8>,
+
+    pending: Vec<FilesystemEvent>,
+    first_event: Option<Instant>,
+    last_event: Option<Instant>,
+}
+
+impl Watcher {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            inotify: Inotify::init()?,
+            watches: Vec::new(),
+            buffer: vec![0; 4096],
+
+            pending: Vec::new(),
+            first_event: None,
+            last_event: None,
+        })
+    }
+
+    /// Watches exactly `directories`, dropping watches that are no longer needed.
+    pub fn watch(&mut self, directories: &[PathBuf]) {
+        let mut watches = std::mem::take(&mut self.watches);
+        let wanted: HashSet<&PathBuf> = directories.iter().collect();
+        watches.retain(|(descriptor, path)| {
+            let keep = wanted.contains(path);
+            if !keep {
+                let _ = self.inotify.watches().remove(descriptor.clone());
+            }
+            keep
+        });
+        let watched: HashSet<PathBuf> = watches.iter().map(|(_, path)| path.clone()).collect();
+        for directory in directories {
+            if watched.contains(directory) {
+                continue;
+            }
+            let mask = WatchMask::CREATE
+                | WatchMask::DELETE
+                | WatchMask::MOVED_FROM
+                | WatchMask::MOVED_TO
+                | WatchMask::CLOSE_WRITE
+                | WatchMask::ATTRIB
+                | WatchMask::ONLYDIR;
+            if let Ok(descriptor) = self.inotify.watches().add(directory, mask) {
+                watches.push((descriptor, directory.clone()));
+            }
+        }
+        self.watches = watches;
+    }
+
+    /// Returns the pending events once they have settled, otherwise nothing.
+    pub fn poll(&mut self) -> Vec<FilesystemEvent> {
+        self.read_events();
+
+        let now = Instant::now();
+        match (self.first_event, self.last_event) {
+            (Some(first), Some(last)) if now - last >= DEBOUNCE || now - first >= MAX_DELAY => {
+                self.first_event = None;
+                self.last_event = None;
+                std::mem::take(&mut self.pending)
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn read_events(&mut self) {
+        loop {
+            let events = match self.inotify.read_events(&mut self.buffer) {
+                Ok(events) => events,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return,
+                Err(_) => return,
+            };
+            let mut read_any = false;
+            let mut moved_from: Vec<(u32, PathBuf, OsString)> = Vec::new();
+            for event in events {
+                read_any = true;
+                if event.mask.contains(EventMask::Q_OVERFLOW) {
+                    self.pending.push(FilesystemEvent::Overflow);
+                    continue;
+                }
+                let Some(directory) = self
+                    .watches
+                    .iter()
+                    .find(|(descriptor, _)| *descriptor == event.wd)
+                    .map(|(_, path)| path.clone())
+                else {
+                    continue;
+                };
+                let Some(name) = event.name.map(|name| name.to_os_string()) else {
+                    continue;
+                };
+
+                if event.mask.contains(EventMask::MOVED_FROM) {
+                    moved_from.push((event.cookie, directory, name));
+                } else if event.mask.contains(EventMask::MOVED_TO) {
+                    match moved_from.iter().position(|(cookie, from_directory, _)| {
+                        *cookie == event.cookie && *from_directory == directory
+                    }) {
+                        Some(index) => {
+                            let (_, _, from) = moved_from.remove(index);
+                            self.pending.push(FilesystemEvent::Renamed {
+                                directory,
+                                from,
+                                to: name,
+                            });
+                        }
+                        None => self
+                            .pending
+                            .push(FilesystemEvent::Added { directory, name }),
+                    }
+                } else if event.mask.contains(EventMask::CREATE) {
+                    self.pending
+                        .push(FilesystemEvent::Added { directory, name });
+                } else if event.mask.contains(EventMask::DELETE) {
+                    self.pending
+                        .push(FilesystemEvent::Removed { directory, name });
+                } else if event
+                    .mask
+                    .intersects(EventMask::CLOSE_WRITE | EventMask::ATTRIB)
+                {
+                    self.pending
+                        .push(FilesystemEvent::Modified { directory, name });
+                }
+            }
+            // Moved out of the watched directories, or the pair was split
+            // across reads; either way it is gone from where it was.
+            for (_, directory, name) in moved_from {
+                self.pending
+                    .push(FilesystemEvent::Removed { directory, name });
+            }
+
+            if !read_any {
+                return;
+            }
+            let now = Instant::now();
+            self.first_event.get_or_insert(now);
+            self.last_event = Some(now);
+        }
+    }
+}
+
+impl App {
+    pub fn update_watches(&mut self) {
+        let mut directories = vec![self.current_directory.clone()];
+        if let Some(parent) = self.parent_directory() {
+            directories.push(parent);
+        }
+        if let Some(selected) = &self.selected_item {
+            if selected.is_dir() {
+                directories.push(selected.clone());
+            }
+        }
+        // The directories whose entries the tree or flattened listing shows.
+        if self.virtual_listing.is_some() && self.tree_view {
+            directories.extend(
+                self.expanded
+                    .iter()
+                    .filter(|directory| directory.starts_with(&self.current_directory))
+                    .cloned(),
+            );
+        } else if self.virtual_listing.is_some() && self.flatten {
+            directories.extend(
+                self.current_directory_contents
+                    .iter()
+                    .chain(&self.filtered_out)
+                    .filter(|file| {
+                        file.ftype == FileType::Directory
+                            && Path::new(&file.name).components().count() < self.flatten_depth
+                    })
+                    .take(MAX_FLATTEN_WATCHES)
+                    .map(|file| self.current_directory.join(&file.name)),
+            );
+        }
+        if let Some(watcher) = &mut self.watcher {
+            watcher.watch(&directories);
+        }
+    }
+
+    pub fn handle_filesystem_events(&mut self) {
+        let events = match &mut self.watcher {
+            Some(watcher) => watcher.poll(),
+            None => return,
+        };
+        if events.is_empty() {
+            return;
+        }
+
+        let mut selected_name = self.selected_name();
+        let mut refresh_preview = false;
+        let mut ignore_files_changed = false;
+        for event in events {
+            match event {
+                FilesystemEvent::Added { directory, name } => {
+                    ignore_files_changed |= is_ignore_file(&name);
+                    if self.hides_ignored(&directory, &name) {
+                        continue;
+                    }
+                    self.apply_to_listings(
+                        &directory,
+                        |files, root, prefix, show_hidden, order| {
+                            insert_file(
+                                files,
+                                root,
+                                prefix.join(&name).as_os_str(),
+                                show_hidden,
+                                order,
+                            )
+                        },
+                    );
+                    refresh_preview |= self.is_previewed(&directory, None);
+                }
+                FilesystemEvent::Removed { directory, name } => {
+                    ignore_files_changed |= is_ignore_file(&name);
+                    self.apply_to_listings(&directory, |files, _, prefix, _, _| {
+                        remove_path(files, prefix.join(&name).as_os_str())
+                    });
+                    refresh_preview |= self.is_previewed(&directory, Some(&name));
+                }
+                FilesystemEvent::Renamed {
+                    directory,
+                    from,
+                    to,
+                } => {
+                    ignore_files_changed |= is_ignore_file(&from) || is_ignore_file(&to);
+                    let hidden = self.hides_ignored(&directory, &to);
+                    self.apply_to_listings(
+                        &directory,
+                        |files, root, prefix, show_hidden, order| {
+                            remove_path(files, prefix.join(&from).as_os_str());
+                            if !hidden {
+                                let to = prefix.join(&to);
+                                insert_file(files, root, to.as_os_str(), show_hidden, order);
+                            }
+                        },
+                    );
+                    if directory == self.current_directory
+                        && selected_name.as_deref() == Some(from.as_os_str())
+                    {
+                        selected_name = Some(to);
+                        refresh_preview = true;
+                    }
+                    refresh_preview |= self.is_previewed(&directory, None);
+                }
+                FilesystemEvent::Modified { directory, name } => {
+                    ignore_files_changed |= is_ignore_file(&name);
+                    // Re-reading the entry refreshes its metadata and moves it if
+                    // the listing is sorted by size or time.
+                    self.apply_to_listings(
+                        &directory,
+                        |files, root, prefix, show_hidden, order| {
+                            let name = prefix.join(&name);
+                            if files.iter().any(|file| file.name == name.as_os_str()) {
+                                insert_file(files, root, name.as_os_str(), show_hidden, order);
+                            }
+                        },
+                    );
+                    refresh_preview |= self.is_previewed(&directory, Some(&name));
+                }
+                FilesystemEvent::Overflow => {
+                    self.reload_listings();
+                    refresh_preview = true;
+                }
+            }
+        }
+
+        if ignore_files_changed && self.hide_ignored {
+            self.ignore_rules = IgnoreRules::default();
+            self.reload_listings();
+            refresh_preview = true;
+        }
+
+        self.update_free_space();
+        self.refresh_git_status();
+
+        let previous_selection = self.current_selection;
+        self.current_selection = selected_name
+            .and_then(|name| {
+                self.current_directory_contents
+                    .iter()
+                    .position(|file| file.name == name)
+            })
+            .unwrap_or(previous_selection)
+            .min(self.current_directory_contents.len().saturating_sub(1));
+
+        let selected_file_changed = self.selected_item
+            != self
+                .current_directory_contents
+                .get(self.current_selection)
+                .map(|file| self.current_directory.join(&file.name));
+        if refresh_preview || selected_file_changed {
+            self.update_selected_item();
+        }
+    }
+
+    /// Applies a change in `directory` to the listings showing it. `apply`
+    /// gets the listing, the directory its names are relative to and the
+    /// path in front of the names from `directory`, which is empty unless a
+    /// tree or flattened listing shows a directory below the current one.
+    fn apply_to_listings(
+        &mut self,
+        directory: &Path,
+        mut apply: impl FnMut(&mut Vec<File>, &Path, &Path, bool, &SortOrder),
+    ) {
+        let current_prefix = match self.virtual_listing {
+            None => (directory == self.current_directory).then(PathBuf::new),
+            Some(_) => self.virtual_prefix(directory),
+        };
+        if let Some(prefix) = current_prefix {
+            let order = self.sort_order(&self.current_directory);
+            self.unfilter();
+            apply(
+                &mut self.current_directory_contents,
+                &self.current_directory,
+                &prefix,
+                self.show_hidden,
+                &order,
+            );
+            if self.flatten && self.flatten_files_only {
+                self.current_directory_contents
+                    .retain(|file| file.ftype != FileType::Directory);
+            }
+            self.apply_filter();
+        }
+        if Some(directory) == self.parent_directory().as_deref() {
+            let order = self.sort_order(directory);
+            apply(
+                &mut self.parent_directory_contents,
+                directory,
+                Path::new(""),
+                self.show_hidden,
+                &order,
+            );
+        }
+    }
+
+    /// Where the entries of `directory` go in a tree or flattened listing,
+    /// if it shows them.
+    fn virtual_prefix(&self, directory: &Path) -> Option<PathBuf> {
+        let prefix = directory.strip_prefix(&self.current_directory).ok()?;
+        let shown = if self.tree_view {
+            self.tree_shows_contents(prefix)
+        } else {
+            self.flatten && prefix.components().count() < self.flatten_depth
+        };
+        shown.then(|| prefix.to_path_buf())
+    }
+
+    /// Whether a change to `name` in `directory` (or to the directory itself)
+    /// affects the preview of the selected item.
+    fn is_previewed(&self, directory: &Path, name: Option<&OsStr>) -> bool {
+        let Some(selected) = &self.selected_item else {
+            return false;
+        };
+        selected == directory || name.is_some_and(|name| *selected == directory.join(name))
+    }
+}
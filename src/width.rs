@@ -0,0 +1,207 @@
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::ansi;
+
+/// Columns a grapheme cluster takes in the terminal. Handles East Asian Width,
+/// combining marks, zero-width characters and emoji presentation sequences;
+/// terminals never draw a single cluster wider than two columns.
+pub fn grapheme_width(grapheme: &str) -> usize {
+    grapheme.width().min(2)
+}
+
+pub fn display_width(input: &str) -> usize {
+    input.graphemes(true).map(grapheme_width).sum()
+}
+
+/// A grapheme cluster, or a whole escape sequence coloring the text around it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Piece<'a> {
+    Text(&'a str),
+    Escape(&'a str),
+}
+
+/// Splits `input` into grapheme clusters and escape sequences, with their
+/// byte offsets.
+pub fn pieces(input: &str) -> impl Iterator<Item = (usize, Piece<'_>)> {
+    let mut graphemes = input.grapheme_indices(true);
+    std::iter::from_fn(move || {
+        let (start, grapheme) = graphemes.next()?;
+        if grapheme != "\x1B" {
+            return Some((start, Piece::Text(grapheme)));
+        }
+        let mut end = start + grapheme.len();
+        for (offset, next) in graphemes.by_ref() {
+            end = offset + next.len();
+            if next.ends_with(|c: char| c.is_ascii_alphabetic()) {
+                break;
+            }
+        }
+        Some((start, Piece::Escape(&input[start..end])))
+    })
+}
+
+/// Width of `input` without the escape sequences coloring it.
+pub fn visible_width(input: &str) -> usize {
+    pieces(input)
+        .map(|(_, piece)| match piece {
+            Piece::Text(grapheme) => grapheme_width(grapheme),
+            Piece::Escape(_) => 0,
+        })
+        .sum()
+}
+
+/// Pads or truncates to exactly `max_length` columns, underlining the
+/// graphemes that start inside one of the byte ranges in `highlights`.
+pub fn truncate_highlighted(input: &str, highlights: &[Range<usize>], max_length: usize) -> String {
+    if max_length == 0 {
+        return String::new();
+    }
+    let mut output = String::with_capacity(input.len());
+    let truncated = visible_width(input) > max_length;
+    let limit = if truncated {
+        max_length - 1
+    } else {
+        max_length
+    };
+    // Never split a grapheme cluster, pad instead if a wide one doesn't fit.
+    let mut used = 0;
+    let mut highlighted = false;
+    let mut colored = false;
+    for (offset, piece) in pieces(input) {
+        let grapheme = match piece {
+            Piece::Text(grapheme) => grapheme,
+            Piece::Escape(escape) => {
+                colored = true;
+                output.push_str(escape);
+                continue;
+            }
+        };
+        let grapheme_width = grapheme_width(grapheme);
+        if used + grapheme_width > limit {
+            break;
+        }
+        let in_match = highlights.iter().any(|range| range.contains(&offset));
+        if in_match != highlighted {
+            output.push_str(match in_match {
+                true => ansi::HIGHLIGHT,
+                false => ansi::NO_HIGHLIGHT,
+            });
+            highlighted = in_match;
+        }
+        output.push_str(grapheme);
+        used += grapheme_width;
+    }
+    if highlighted {
+        output.push_str(ansi::NO_HIGHLIGHT);
+    }
+    if colored {
+        output.push_str(ansi::RESET);
+    }
+    if truncated {
+        output.push('…');
+        used += 1;
+    }
+    output.push_str(&" ".repeat(max_length - used));
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Names mixing scripts, with their width in columns.
+    const CORPUS: &[(&str, usize)] = &[
+        ("readme.txt", 10),
+        ("日本語.txt", 10),
+        ("한국어", 6),
+        ("ｆｕｌｌ", 8),
+        ("Привет.md", 9),
+        ("cafe\u{301}", 4),
+        ("a\u{300}\u{301}\u{302}b", 2),
+        ("👨\u{200D}👩\u{200D}👧", 2),
+        ("🏳\u{FE0F}\u{200D}🌈.png", 6),
+        ("❤\u{FE0F}", 2),
+        ("❤", 1),
+        ("отчёт_報告_🎉.pdf", 17),
+        ("\u{200B}zero", 4),
+    ];
+
+    #[test]
+    fn measures_the_corpus() {
+        for (name, width) in CORPUS {
+            assert_eq!(display_width(name), *width, "{name:?}");
+            assert_eq!(visible_width(name), *width, "{name:?}");
+        }
+    }
+
+    #[test]
+    fn measures_clusters() {
+        assert_eq!(grapheme_width("a"), 1);
+        assert_eq!(grapheme_width("語"), 2);
+        assert_eq!(grapheme_width("e\u{301}"), 1);
+        assert_eq!(grapheme_width("\u{301}"), 0);
+        assert_eq!(grapheme_width("👨\u{200D}👩\u{200D}👧\u{200D}👦"), 2);
+        assert_eq!(grapheme_width("☺\u{FE0F}"), 2);
+    }
+
+    #[test]
+    fn ignores_escapes() {
+        assert_eq!(visible_width("\x1B[38;5;12m日本\x1B[0m"), 4);
+    }
+
+    #[test]
+    fn truncation_fills_the_width_exactly() {
+        for (name, _) in CORPUS {
+            for max_length in 0..20 {
+                let truncated = truncate_highlighted(name, &[], max_length);
+                assert_eq!(display_width(&truncated), max_length, "{name:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn truncation_never_splits_a_cluster() {
+        for (name, _) in CORPUS {
+            for max_length in 0..20 {
+                let truncated = truncate_highlighted(name, &[], max_length);
+                let kept = truncated.trim_end_matches(' ').trim_end_matches('…');
+                let clusters: Vec<&str> = name.graphemes(true).collect();
+                let kept_clusters: Vec<&str> = kept.graphemes(true).collect();
+                assert_eq!(
+                    kept_clusters,
+                    clusters[..kept_clusters.len()],
+                    "{name:?} cut to {max_length}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn pads_when_a_wide_cluster_does_not_fit() {
+        assert_eq!(truncate_highlighted("日本語.txt", &[], 6), "日本… ");
+        assert_eq!(
+            truncate_highlighted("ab👨\u{200D}👩\u{200D}👧", &[], 3),
+            "ab…"
+        );
+        assert_eq!(truncate_highlighted("日本", &[], 5), "日本 ");
+    }
+
+    #[test]
+    fn narrow_columns_keep_their_width() {
+        assert_eq!(truncate_highlighted("readme.txt", &[], 0), "");
+        assert_eq!(truncate_highlighted("readme.txt", &[], 1), "…");
+        assert_eq!(truncate_highlighted("readme.txt", &[], 2), "r…");
+        assert_eq!(truncate_highlighted("日本語", &[], 2), "… ");
+        assert_eq!(truncate_highlighted("ab", &[], 2), "ab");
+        assert_eq!(truncate_highlighted("a", &[], 2), "a ");
+    }
+
+    #[test]
+    fn truncation_keeps_short_names() {
+        assert_eq!(truncate_highlighted("Привет", &[], 8), "Привет  ");
+        assert_eq!(truncate_highlighted("cafe\u{301}", &[], 4), "cafe\u{301}");
+    }
+}